@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Cursor, Read},
+    path::Path,
+};
+
+use anyhow::{Result, anyhow, bail};
+use clap::ValueEnum;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use log::debug;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+    TarGz,
+}
+
+pub const CHECKSUMS_ENTRY: &str = "checksums.txt";
+
+/// Extensions of formats that are already compressed, deflating them again only costs time.
+const STORED_EXTENSIONS: [&str; 14] = [
+    "png", "jpg", "jpeg", "gif", "webp", "ogg", "mp3", "opus", "flac", "zip", "gz", "zst", "br",
+    "woff2",
+];
+
+/// How a zip entry is compressed, tarballs are compressed as a whole or not at all.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryCompression {
+    Stored,
+    Deflated,
+}
+
+impl EntryCompression {
+    /// Picks the method for an entry by its extension, `overrides` maps lowercase
+    /// extensions to a method and takes precedence.
+    pub(crate) fn for_entry(name: &str, overrides: &HashMap<String, Self>) -> Self {
+        let extension = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match overrides.get(&extension) {
+            Some(compression) => *compression,
+            None if STORED_EXTENSIONS.contains(&extension.as_str()) => Self::Stored,
+            None => Self::Deflated,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl ChecksumAlgo {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        Self::from_str(name, true).map_err(|_| anyhow!("unknown checksum algorithm {name:?}"))
+    }
+
+    pub(crate) fn digest(self, data: &[u8]) -> String {
+        match self {
+            Self::Sha256 => format!("{:x}", Sha256::digest(data)),
+            Self::Sha512 => format!("{:x}", Sha512::digest(data)),
+            Self::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+
+    /// Formats a line of the checksums file, `<algo>:<digest>  <entry>`.
+    pub(crate) fn checksum_line(self, name: &str, data: &[u8]) -> String {
+        format!("{}:{}  {name}\n", self.name(), self.digest(data))
+    }
+}
+
+/// Rejects entry names that could escape the destination directory when extracted.
+pub(crate) fn sanitize_entry_name(name: &str) -> Result<&str> {
+    let bytes = name.as_bytes();
+
+    if name.is_empty() {
+        bail!("archive entry has an empty name");
+    }
+    if name.contains('\0') {
+        bail!("archive entry {name:?} contains a NUL byte");
+    }
+    if name.starts_with(['/', '\\']) {
+        bail!("archive entry {name:?} is an absolute path");
+    }
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        bail!("archive entry {name:?} has a drive prefix");
+    }
+    if name.split(['/', '\\']).any(|component| component == "..") {
+        bail!("archive entry {name:?} escapes the archive root");
+    }
+
+    Ok(name)
+}
+
+/// Strips the folder every entry was packed under with `pack --archive-prefix`, found as
+/// the one holding crate.json when there is none at the root.
+fn strip_archive_prefix(entries: &mut [(String, Vec<u8>)]) {
+    if entries.iter().any(|(name, _)| name == "crate.json") {
+        return;
+    }
+    let Some(prefix) = entries
+        .iter()
+        .filter_map(|(name, _)| name.strip_suffix("crate.json"))
+        .filter(|prefix| prefix.ends_with('/'))
+        .min_by_key(|prefix| prefix.len())
+        .map(str::to_string)
+    else {
+        return;
+    };
+
+    if entries.iter().all(|(name, _)| name.starts_with(&prefix)) {
+        for (name, _) in entries {
+            name.drain(..prefix.len());
+        }
+    }
+}
+
+/// A packed crate read fully into memory, regardless of its archive format.
+pub(crate) struct CrateArchive {
+    entries: Vec<(String, Vec<u8>)>,
+    comment: String,
+}
+
+impl CrateArchive {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        Self::from_bytes(bytes).map_err(|e| anyhow!("failed to open {path:?} as a crate: {e}"))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.starts_with(b"\x1f\x8b") {
+            let mut tar = Vec::new();
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut tar)?;
+            return Self::from_tar(&tar);
+        }
+
+        if bytes.get(257..262) == Some(b"ustar") {
+            return Self::from_tar(&bytes);
+        }
+
+        Self::from_zip(bytes)
+    }
+
+    fn from_zip(bytes: Vec<u8>) -> Result<Self> {
+        let mut zip = ZipArchive::new(Cursor::new(bytes))?;
+        let comment = String::from_utf8_lossy(zip.comment()).into_owned();
+
+        let mut entries = Vec::new();
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            entries.push((sanitize_entry_name(entry.name())?.to_string(), buf));
+        }
+        strip_archive_prefix(&mut entries);
+
+        Ok(Self { entries, comment })
+    }
+
+    fn from_tar(bytes: &[u8]) -> Result<Self> {
+        let mut tar = tar::Archive::new(bytes);
+
+        let mut entries = Vec::new();
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let name =
+                sanitize_entry_name(&String::from_utf8_lossy(&entry.path_bytes()))?.to_string();
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            entries.push((name, buf));
+        }
+        strip_archive_prefix(&mut entries);
+
+        Ok(Self {
+            entries,
+            comment: String::new(),
+        })
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+    }
+
+    pub(crate) fn read_entry(&self, name: &str) -> Result<&[u8]> {
+        self.entries()
+            .find(|(entry, _)| *entry == name)
+            .map(|(_, data)| data)
+            .ok_or_else(|| anyhow!("missing `{name}` entry"))
+    }
+
+    pub(crate) fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Checks every entry against the checksums file, if the crate has one, and returns the
+    /// algorithm it uses.
+    pub(crate) fn verify_checksums(&self) -> Result<Option<ChecksumAlgo>> {
+        let Result::Ok(checksums) = self.read_entry(CHECKSUMS_ENTRY) else {
+            return Ok(None);
+        };
+        let checksums = str::from_utf8(checksums)
+            .map_err(|e| anyhow!("{CHECKSUMS_ENTRY} is not valid UTF-8: {e}"))?;
+
+        let mut algo = None;
+        let mut covered = HashSet::new();
+        for line in checksums.lines().filter(|l| !l.trim().is_empty()) {
+            let (checksum, name) = line
+                .split_once("  ")
+                .ok_or_else(|| anyhow!("{CHECKSUMS_ENTRY}: malformed line {line:?}"))?;
+            let (line_algo, digest) = checksum
+                .split_once(':')
+                .ok_or_else(|| anyhow!("{CHECKSUMS_ENTRY}: missing algorithm in {line:?}"))?;
+            let line_algo = ChecksumAlgo::from_name(line_algo)?;
+
+            if line_algo.digest(self.read_entry(name)?) != digest {
+                bail!("checksum mismatch for `{name}`");
+            }
+            covered.insert(name);
+            algo = Some(line_algo);
+        }
+
+        if let Some((name, _)) = self
+            .entries()
+            .find(|(name, _)| *name != CHECKSUMS_ENTRY && !covered.contains(name))
+        {
+            bail!("`{name}` is not listed in {CHECKSUMS_ENTRY}");
+        }
+
+        Ok(algo)
+    }
+
+    /// SHA-256 over the entries sorted by name, each hashed as its name, a NUL byte, its
+    /// length as a little-endian u64 and its contents. Archive format, compression, entry
+    /// order and the provenance comment don't affect it.
+    pub(crate) fn content_hash(&self) -> String {
+        let mut entries = self.entries().collect::<Vec<_>>();
+        entries.sort_by_key(|(name, _)| *name);
+
+        let mut hasher = Sha256::new();
+        for (name, data) in entries {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update((data.len() as u64).to_le_bytes());
+            hasher.update(data);
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub(crate) fn crate_json(&self) -> Result<Value> {
+        serde_json::from_slice(self.read_entry("crate.json")?)
+            .map_err(|e| anyhow!("failed to parse crate.json: {e}"))
+    }
+}
+
+pub(crate) enum ArchiveWriter {
+    Zip(ZipWriter<File>),
+    Tar(tar::Builder<File>),
+    TarGz(tar::Builder<GzEncoder<File>>),
+}
+
+impl ArchiveWriter {
+    pub(crate) fn new(file: File, format: ArchiveFormat) -> Self {
+        match format {
+            ArchiveFormat::Zip => Self::Zip(ZipWriter::new(file)),
+            ArchiveFormat::Tar => Self::Tar(tar::Builder::new(file)),
+            ArchiveFormat::TarGz => Self::TarGz(tar::Builder::new(GzEncoder::new(
+                file,
+                Compression::default(),
+            ))),
+        }
+    }
+
+    /// Only zip archives can carry a comment, it is dropped for tarballs.
+    pub(crate) fn set_comment(&mut self, comment: String) {
+        if let Self::Zip(zip) = self {
+            zip.set_comment(comment);
+        }
+    }
+
+    pub(crate) fn write_entry(
+        &mut self,
+        name: &str,
+        data: &mut dyn Read,
+        size: u64,
+        compression: EntryCompression,
+    ) -> Result<()> {
+        fn tar_header(size: u64) -> tar::Header {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size);
+            header.set_mode(0o644);
+            header.set_cksum();
+            header
+        }
+
+        match self {
+            Self::Zip(zip) => {
+                debug!("compressing {name} as {compression:?}");
+                let method = match compression {
+                    EntryCompression::Stored => CompressionMethod::Stored,
+                    EntryCompression::Deflated => CompressionMethod::Deflated,
+                };
+                zip.start_file(
+                    name,
+                    SimpleFileOptions::default().compression_method(method),
+                )?;
+                io::copy(data, zip)?;
+            }
+            Self::Tar(tar) => tar.append_data(&mut tar_header(size), name, data)?,
+            Self::TarGz(tar) => tar.append_data(&mut tar_header(size), name, data)?,
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            Self::Zip(zip) => {
+                zip.finish()?;
+            }
+            Self::Tar(tar) => {
+                tar.into_inner()?;
+            }
+            Self::TarGz(tar) => {
+                tar.into_inner()?.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::commands::{export::run_export_command, info::run_info_command};
+
+    /// Writes a zip crate with a valid crate.json and wasm plus one extra `entry`.
+    fn crafted_crate(dir: &TempDir, entry: &str) -> String {
+        let path = dir.path().join("evil.gbcrate");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in [
+            ("crate.json", br#"{"name":"evil"}"#.as_slice()),
+            ("app.wasm", b"\0asm\x01\0\0\0".as_slice()),
+            (entry, b"pwned".as_slice()),
+        ] {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn rejects_malicious_entry_names() {
+        for entry in ["../x", "a/../../x", "/etc/x", "\\x", "C:x", "c:/x"] {
+            assert!(
+                sanitize_entry_name(entry).is_err(),
+                "{entry:?} was accepted"
+            );
+        }
+        for entry in ["app.wasm", "assets/a..b.png", "assets/.hidden"] {
+            assert!(sanitize_entry_name(entry).is_ok(), "{entry:?} was rejected");
+        }
+    }
+
+    #[test]
+    fn export_and_info_reject_crafted_archives() {
+        for entry in ["../x", "/tmp/x", "C:/x"] {
+            let dir = TempDir::new().unwrap();
+            let crate_path = crafted_crate(&dir, entry);
+            let out = dir.path().join("out");
+            std::fs::create_dir(&out).unwrap();
+
+            let error = run_export_command(&crate_path, None, Some(out.display().to_string()))
+                .expect_err("export accepted a crafted archive")
+                .to_string();
+            assert!(error.contains(entry), "{error}");
+            assert!(run_info_command(&crate_path).is_err());
+            assert!(out.read_dir().unwrap().next().is_none());
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Result, bail};
+use clap::builder::styling::Style;
+use clap_cargo::style;
+use log::info;
+use regex::Regex;
+
+use crate::{
+    commands::verify::diagnose_crate,
+    schema::{Diagnostic, Severity},
+    utils::{determine_path, get_gooseboy_crates_folder, installed_crates},
+};
+
+/// Verifies every installed crate, those of other targets included, and fails when any of
+/// them is corrupt or uses a crate.json format this CLI doesn't understand.
+pub fn run_audit_command(path: Option<String>, name_pattern: &Regex, color: bool) -> Result<()> {
+    let folder = determine_path(path, get_gooseboy_crates_folder()?);
+    let paint = |style: Style| if color { style } else { Style::new() };
+
+    let crate_paths = installed_crates(&folder)?
+        .into_iter()
+        .map(|(crate_path, _)| crate_path)
+        .collect::<Vec<_>>();
+
+    let (mut corrupt, mut unsupported) = (0, 0);
+    for crate_path in &crate_paths {
+        let diagnostics = match diagnose_crate(crate_path, name_pattern) {
+            Ok((diagnostics, _)) => diagnostics,
+            Err(e) => vec![Diagnostic::error("", e.to_string()).in_file("archive")],
+        };
+        if diagnostics.is_empty() {
+            continue;
+        }
+
+        let unknown_schema = diagnostics.iter().any(|d| d.field == "schema_version");
+        let errors = diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error && d.field != "schema_version");
+        if errors {
+            corrupt += 1;
+        } else if unknown_schema {
+            unsupported += 1;
+        }
+
+        let header = paint(style::HEADER);
+        println!("{header}{}{header:#}", crate_path.display());
+        for diagnostic in diagnostics {
+            let (label, style) = match diagnostic.severity {
+                Severity::Error => ("error", paint(style::ERROR)),
+                Severity::Warning => ("warning", paint(style::WARN)),
+            };
+            println!("  {style}{label}{style:#}: {diagnostic}");
+        }
+    }
+
+    info!(
+        "audited {} crate(s) in {folder:?}: {} ok, {corrupt} corrupt, {unsupported} with an unsupported schema version",
+        crate_paths.len(),
+        crate_paths.len() - corrupt - unsupported
+    );
+    if corrupt + unsupported > 0 {
+        bail!("{} crate(s) failed the audit", corrupt + unsupported);
+    }
+
+    Ok(())
+}
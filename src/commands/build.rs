@@ -1,28 +1,573 @@
-use std::path::PathBuf;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Ok, Result};
+use anyhow::{Context, Ok, Result, anyhow, bail};
+use clap::{Args, ValueEnum};
+use log::{debug, trace, warn};
+use semver::Version;
+use serde_json::Value;
 
-use crate::utils::{TARGET, resolve_path_and_package, resolve_project_dir, run_command};
+use crate::utils::{
+    CrateType, TARGET, get_cargo_metadata, get_default_members, get_project_name,
+    get_workspace_members, read_crate_json, resolve_path_and_package, resolve_project_dir,
+    run_command, run_command_output, run_command_with_retries,
+};
+
+pub const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    /// Forward cargo's JSON messages to stdout, followed by gooseboy's own
+    Json,
+}
+
+/// A wasm file cargo reported in a `compiler-artifact` message.
+pub struct WasmArtifact {
+    pub manifest_path: PathBuf,
+    pub path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct BuildArgs {
+    #[arg(short, long)]
+    pub release: bool,
+    /// Deny all warnings by adding `-D warnings` to RUSTFLAGS
+    #[arg(long)]
+    pub fail_on_warnings: bool,
+    /// Set an environment variable for the build, can be repeated
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env)]
+    pub envs: Vec<(String, String)>,
+    /// Build every member of the workspace
+    #[arg(long)]
+    pub workspace: bool,
+    /// Build with `panic = "abort"` to drop the unwinding machinery from the wasm
+    #[arg(long)]
+    pub panic_abort: bool,
+    /// Initial linear memory in bytes, a multiple of the 64 KiB wasm page size
+    #[arg(long, value_parser = parse_memory_size)]
+    pub initial_memory: Option<u64>,
+    /// Maximum linear memory in bytes, a multiple of the 64 KiB wasm page size
+    #[arg(long, value_parser = parse_memory_size)]
+    pub max_memory: Option<u64>,
+    /// Stack size in bytes, 16-byte aligned
+    #[arg(long, value_parser = parse_stack_size)]
+    pub stack_size: Option<u64>,
+    /// Experimental: build and pack a `bin` target instead of the cdylib
+    #[arg(long, value_enum, default_value_t)]
+    pub crate_type: CrateType,
+    /// Run `cargo clean` for the selected packages and target before building
+    #[arg(long)]
+    pub fresh: bool,
+    /// Rerun cargo up to this many times when it fails, e.g. on file lock contention
+    #[arg(long, default_value_t = 0)]
+    pub build_retries: u32,
+    /// Build even when the toolchain is older than the crate's `rust-version`, e.g. to try a
+    /// preview toolchain
+    #[arg(long)]
+    pub ignore_rust_version: bool,
+    /// Fail before building when the standard library of the target isn't installed, instead
+    /// of leaving it to cargo
+    #[arg(long)]
+    pub require_target: bool,
+    /// Kill cargo if it runs longer than this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub build_timeout: Option<u64>,
+    /// Format of the build messages, `json` streams cargo's JSON messages for editors
+    #[arg(long, value_enum, default_value_t)]
+    pub build_message_format: MessageFormat,
+    /// Features to enable, added to the `features` listed in crate.json
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    pub features: Vec<String>,
+    /// Don't enable the default features, also set by `"default_features": false` in crate.json
+    #[arg(long)]
+    pub no_default_features: bool,
+    /// Advanced, nightly only: rebuild `core` and `alloc` with the crate's profile for a
+    /// smaller wasm, also enabled by `"build_std": true` in crate.json
+    #[arg(long)]
+    pub build_std: bool,
+    /// Number of parallel jobs, also bounds how many crates are packed at once
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+    /// Target triple to build for, changed per triple by `pack --all-targets`
+    #[arg(skip = TARGET.to_string())]
+    pub target: String,
+    /// When `pack --time-limit` runs out, cargo is killed once it is reached
+    #[arg(skip)]
+    pub deadline: Option<Instant>,
+}
+
+fn parse_env(s: &str) -> Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected KEY=VALUE, got {s:?}"))?;
+
+    if key.is_empty() {
+        bail!("environment variable name must not be empty");
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_memory_size(s: &str) -> Result<u64> {
+    let size = s.parse::<u64>()?;
+    if size % WASM_PAGE_SIZE != 0 {
+        bail!("{size} is not a multiple of the wasm page size ({WASM_PAGE_SIZE} bytes)");
+    }
+    Ok(size)
+}
+
+fn parse_stack_size(s: &str) -> Result<u64> {
+    let size = s.parse::<u64>()?;
+    if size % 16 != 0 {
+        bail!("{size} is not 16-byte aligned");
+    }
+    Ok(size)
+}
+
+fn link_args(args: &BuildArgs) -> Vec<String> {
+    let mut link_args = Vec::new();
+
+    if let Some(size) = args.initial_memory {
+        link_args.push(format!("--initial-memory={size}"));
+    }
+    if let Some(size) = args.max_memory {
+        link_args.push(format!("--max-memory={size}"));
+    }
+    if let Some(size) = args.stack_size {
+        link_args.push(format!("-zstack-size={size}"));
+    }
+
+    link_args
+}
+
+fn build_envs(args: &BuildArgs) -> Vec<(String, String)> {
+    let mut envs = args.envs.clone();
+
+    let mut extra_flags = link_args(args)
+        .into_iter()
+        .map(|arg| format!("-C link-arg={arg}"))
+        .collect::<Vec<_>>();
+    if !extra_flags.is_empty() {
+        debug!("linker flags: {}", extra_flags.join(" "));
+    }
+    if args.fail_on_warnings {
+        extra_flags.push("-D warnings".to_string());
+    }
+
+    if !extra_flags.is_empty() {
+        let rustflags = envs
+            .iter()
+            .rev()
+            .find(|(k, _)| k == "RUSTFLAGS")
+            .map(|(_, v)| v.clone())
+            .or_else(|| env::var("RUSTFLAGS").ok())
+            .unwrap_or_default();
+        let rustflags = format!("{rustflags} {}", extra_flags.join(" "))
+            .trim_start()
+            .to_string();
+        envs.push(("RUSTFLAGS".to_string(), rustflags));
+    }
+
+    envs
+}
+
+fn parse_rust_version(version: &str) -> Result<Version> {
+    let version = version.split(['-', '+']).next().unwrap_or(version);
+    let mut parts = version.split('.').map(str::parse::<u64>);
+    let mut next = || parts.next().transpose().map(Option::unwrap_or_default);
+
+    Ok(Version::new(next()?, next()?, next()?))
+}
+
+/// Reads the `features` and `default_features` build settings of crate.json.
+fn crate_json_features(path: &Path) -> Result<(Vec<String>, bool)> {
+    let Some(crate_json) = read_crate_json(path)? else {
+        return Ok((Vec::new(), true));
+    };
+
+    let features = match crate_json.get("features") {
+        Some(features) => features
+            .as_array()
+            .and_then(|features| {
+                features
+                    .iter()
+                    .map(|f| f.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| anyhow!("crate.json: `features` must be an array of strings"))?,
+        None => Vec::new(),
+    };
+    let default_features = match crate_json.get("default_features") {
+        Some(default_features) => default_features
+            .as_bool()
+            .ok_or_else(|| anyhow!("crate.json: `default_features` must be a boolean"))?,
+        None => true,
+    };
+
+    Ok((features, default_features))
+}
+
+/// The crate.json features of every package the build selects. With `--package`, or when
+/// several members are built at once, they are qualified as `member/feature` and read from
+/// each member's own crate.json.
+fn build_features(
+    path: &PathBuf,
+    args: &BuildArgs,
+    package: Option<&str>,
+) -> Result<(Vec<String>, bool)> {
+    let qualify = |name: &str, features: Vec<String>| {
+        features
+            .into_iter()
+            .map(|feature| format!("{name}/{feature}"))
+            .collect::<Vec<_>>()
+    };
+
+    if let Some(package) = package {
+        let metadata = get_cargo_metadata(path, false)?;
+        let member = get_workspace_members(&metadata)
+            .into_iter()
+            .find(|member| get_project_name(member, &metadata).is_ok_and(|name| name == package))
+            .ok_or_else(|| anyhow!("package {package} is not a member of the workspace"))?;
+        let (features, default_features) =
+            crate_json_features(&member).map_err(|e| anyhow!("{package}: {e}"))?;
+        return Ok((qualify(package, features), default_features));
+    }
+
+    let members = if args.workspace {
+        Some(get_workspace_members(&get_cargo_metadata(path, false)?))
+    } else {
+        get_default_members(path)?
+    };
+    let Some(members) = members else {
+        return crate_json_features(path);
+    };
+
+    let metadata = get_cargo_metadata(path, false)?;
+    let mut features = Vec::new();
+    for member in members {
+        let name = get_project_name(&member, &metadata)?;
+        let (member_features, default_features) =
+            crate_json_features(&member).map_err(|e| anyhow!("{name}: {e}"))?;
+        // --no-default-features would apply to every member of the build
+        if !default_features {
+            bail!(
+                "{name} sets `\"default_features\": false` in crate.json, which can't be applied to one member of a workspace build, pack it on its own or pass --per-package-build"
+            );
+        }
+        features.extend(qualify(&name, member_features));
+    }
+
+    Ok((features, true))
+}
+
+fn required_rust_version(path: &PathBuf, package: Option<&str>) -> Result<Option<String>> {
+    // the crate.json of the member being built, not the one at the workspace root
+    if let Some(crate_json) = read_crate_json(&resolve_project_dir(path, package)?)?
+        && let Some(version) = crate_json.get("min_rust_version")
+    {
+        return Ok(Some(
+            version
+                .as_str()
+                .ok_or_else(|| anyhow!("crate.json: `min_rust_version` must be a string"))?
+                .to_string(),
+        ));
+    }
+
+    let metadata = get_cargo_metadata(path, false)?;
+    let manifest = fs::canonicalize(path.join("Cargo.toml")).ok();
+    let version = metadata["packages"].as_array().and_then(|packages| {
+        packages
+            .iter()
+            .find(|p| match package {
+                Some(name) => p["name"].as_str() == Some(name),
+                None => p["manifest_path"]
+                    .as_str()
+                    .and_then(|m| fs::canonicalize(m).ok())
+                    .is_some_and(|m| Some(m) == manifest),
+            })
+            .and_then(|p| p["rust_version"].as_str())
+            .map(str::to_string)
+    });
+
+    Ok(version)
+}
+
+/// Whether `--build-std` was passed or crate.json sets `"build_std": true`.
+fn wants_build_std(path: &Path, args: &BuildArgs) -> Result<bool> {
+    if args.build_std {
+        return Ok(true);
+    }
+
+    match read_crate_json(path)?.and_then(|json| json.get("build_std").cloned()) {
+        Some(build_std) => build_std
+            .as_bool()
+            .ok_or_else(|| anyhow!("crate.json: `build_std` must be a boolean")),
+        None => Ok(false),
+    }
+}
+
+fn check_nightly(path: &PathBuf) -> Result<()> {
+    let output = run_command_output(path, "rustc", &["--version"])?;
+    if !output.contains("-nightly") && !output.contains("-dev") {
+        bail!(
+            "--build-std needs a nightly toolchain but the active one is {}, try `rustup override set nightly` and `rustup component add rust-src`",
+            output.trim()
+        );
+    }
+
+    Ok(())
+}
+
+fn check_rust_version(path: &PathBuf, package: Option<&str>) -> Result<()> {
+    let Some(required) = required_rust_version(path, package)? else {
+        return Ok(());
+    };
+
+    let output = run_command_output(path, "rustc", &["--version"])?;
+    let installed = output
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("unexpected `rustc --version` output {output:?}"))?;
+
+    trace!("crate requires rust {required}, found {installed}");
+    if parse_rust_version(installed)? < parse_rust_version(&required)? {
+        bail!(
+            "this crate requires rust {required} or newer but the active toolchain is {installed}, run `rustup update` or pass --ignore-rust-version"
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the active toolchain ships the standard library for `target`, looking in its
+/// sysroot so toolchains not managed by rustup work as well.
+fn check_target_installed(path: &PathBuf, target: &str) -> Result<()> {
+    let sysroot = run_command_output(path, "rustc", &["--print", "sysroot"])?;
+    let target_lib = Path::new(sysroot.trim()).join("lib/rustlib").join(target);
+
+    trace!("looking for the {target} standard library in {target_lib:?}");
+    if !target_lib.is_dir() {
+        bail!(
+            "the {target} target is not installed for the active toolchain, run `rustup target add {target}`"
+        );
+    }
+
+    Ok(())
+}
+
+pub fn build_project(path: &PathBuf, args: &BuildArgs) -> Result<Vec<WasmArtifact>> {
+    build_package(path, args, None)
+}
+
+/// Builds only `package` when given, bypassing `--workspace` and its feature unification.
+pub fn build_package(
+    path: &PathBuf,
+    args: &BuildArgs,
+    package: Option<&str>,
+) -> Result<Vec<WasmArtifact>> {
+    run_cargo(path, "build", args, package)
+}
+
+/// Cleans the build output of the selected packages only, leaving other packages and
+/// targets alone.
+fn clean_packages(path: &PathBuf, args: &BuildArgs, package: Option<&str>) -> Result<()> {
+    let metadata = get_cargo_metadata(path, false)?;
+    let packages = match package {
+        Some(package) => vec![package.to_string()],
+        None if args.workspace => metadata["packages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p["name"].as_str().map(str::to_string))
+            .collect(),
+        None => match get_default_members(path)? {
+            Some(members) => members
+                .iter()
+                .map(|member| get_project_name(member, &metadata))
+                .collect::<Result<_>>()?,
+            None => vec![get_project_name(path, &metadata)?],
+        },
+    };
+
+    let mut clean_args = vec!["clean", "--target", &args.target];
+    if args.release {
+        clean_args.push("--release");
+    }
+    for package in &packages {
+        clean_args.push("--package");
+        clean_args.push(package);
+    }
+
+    trace!("cleaning {}", packages.join(", "));
+    run_command(path, "cargo", &clean_args)
+}
+
+fn wasm_artifacts(message: &Value) -> Vec<WasmArtifact> {
+    if message["reason"] != "compiler-artifact" {
+        return Vec::new();
+    }
+
+    let manifest_path = PathBuf::from(message["manifest_path"].as_str().unwrap_or_default());
+    message["filenames"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .filter(|file| file.ends_with(".wasm"))
+        .map(|file| WasmArtifact {
+            manifest_path: manifest_path.clone(),
+            path: PathBuf::from(file),
+        })
+        .collect()
+}
+
+/// Runs a cargo subcommand (`build` or `check`) with the flags shared by every build,
+/// returning the wasm artifacts cargo reported.
+pub(crate) fn run_cargo(
+    path: &PathBuf,
+    subcommand: &str,
+    args: &BuildArgs,
+    package: Option<&str>,
+) -> Result<Vec<WasmArtifact>> {
+    if args.ignore_rust_version {
+        warn!("not checking the crate's minimum rust version, --ignore-rust-version was passed");
+    } else {
+        check_rust_version(path, package)?;
+    }
+    if args.require_target {
+        check_target_installed(path, &args.target)?;
+    }
+
+    if args.fresh {
+        clean_packages(path, args, package)?;
+    }
 
-pub fn build_project(path: &PathBuf, release: bool) -> Result<()> {
     let mut build_args = Vec::new();
-    build_args.push("build");
+    build_args.push(subcommand);
 
-    if release {
+    // cargo enforces `rust-version` on its own as well
+    if args.ignore_rust_version {
+        build_args.push("--ignore-rust-version");
+    }
+
+    if args.release {
         build_args.push("--release");
     }
 
+    if let Some(package) = package {
+        build_args.push("--package");
+        build_args.push(package);
+    } else if args.workspace {
+        build_args.push("--workspace");
+    }
+
+    if args.crate_type == CrateType::Bin {
+        build_args.push("--bins");
+    }
+
+    let (mut features, default_features) = build_features(path, args, package)?;
+    for feature in &args.features {
+        if !features.contains(feature) {
+            features.push(feature.clone());
+        }
+    }
+    let features = features.join(",");
+    if !features.is_empty() {
+        debug!("features: {features}");
+        build_args.push("--features");
+        build_args.push(&features);
+    }
+    if args.no_default_features || !default_features {
+        build_args.push("--no-default-features");
+    }
+
+    if wants_build_std(path, args)? {
+        check_nightly(path)?;
+        build_args.push("-Z");
+        build_args.push("build-std=core,alloc");
+    }
+
+    let jobs = args.jobs.map(|j| j.to_string());
+    if let Some(jobs) = &jobs {
+        build_args.push("--jobs");
+        build_args.push(jobs);
+    }
+
+    let profile = if args.release { "release" } else { "dev" };
+    let panic_abort = format!("profile.{profile}.panic=\"abort\"");
+    if args.panic_abort {
+        build_args.push("--config");
+        build_args.push(&panic_abort);
+    }
+
     build_args.push("--target");
-    build_args.push(TARGET);
+    build_args.push(&args.target);
 
-    run_command(path, "cargo", &build_args)?;
+    // cargo still renders diagnostics itself with json-render-diagnostics, stdout only
+    // carries the JSON messages we need to find the real wasm filenames
+    let json = args.build_message_format == MessageFormat::Json;
+    build_args.push("--message-format");
+    build_args.push(if json {
+        "json"
+    } else {
+        "json-render-diagnostics"
+    });
 
-    Ok(())
+    let mut artifacts = Vec::new();
+    let mut on_message = |line: &str| match serde_json::from_str::<Value>(line) {
+        Result::Ok(message) => {
+            if json {
+                println!("{line}");
+            }
+            artifacts.extend(wasm_artifacts(&message));
+        }
+        // keep stdout for machine-readable output such as `pack --pipe`
+        Err(_) => eprintln!("{line}"),
+    };
+
+    let mut timeout = args.build_timeout.map(Duration::from_secs);
+    if let Some(deadline) = args.deadline {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            bail!("the time limit was exceeded before cargo {subcommand} started");
+        }
+        timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+    }
+
+    let result = run_command_with_retries(
+        path,
+        "cargo",
+        &build_args,
+        &build_envs(args),
+        args.build_retries,
+        timeout,
+        Some(&mut on_message),
+    );
+    let result = match args.deadline {
+        Some(deadline) if Instant::now() >= deadline => result.with_context(|| {
+            format!("the time limit was exceeded while running cargo {subcommand}")
+        }),
+        _ => result,
+    };
+    if args.fail_on_warnings {
+        result.with_context(|| {
+            format!("{subcommand} failed, note that warnings are denied by --fail-on-warnings")
+        })?;
+    } else {
+        result?;
+    }
+
+    Ok(artifacts)
 }
 
-pub fn run_build_command(release: bool, package: Option<String>) -> anyhow::Result<()> {
+pub fn run_build_command(args: &BuildArgs, package: Option<String>) -> anyhow::Result<()> {
     let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
     let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
-    build_project(&path, release)?;
+    build_project(&path, args)?;
     Ok(())
 }
@@ -0,0 +1,13 @@
+use anyhow::{Ok, Result};
+
+use crate::{
+    commands::build::{BuildArgs, run_cargo},
+    utils::{resolve_path_and_package, resolve_project_dir},
+};
+
+pub fn run_check_command(args: &BuildArgs, package: Option<String>) -> Result<()> {
+    let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
+    let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
+    run_cargo(&path, "check", args, None)?;
+    Ok(())
+}
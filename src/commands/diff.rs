@@ -0,0 +1,98 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+use crate::archive::CrateArchive;
+
+struct CrateSummary {
+    entries: BTreeMap<String, Vec<u8>>,
+    crate_json: Map<String, Value>,
+    wasm_entry: Option<String>,
+}
+
+fn read_summary(crate_path: &str) -> Result<CrateSummary> {
+    let archive = CrateArchive::open(Path::new(crate_path))?;
+
+    let entries: BTreeMap<_, _> = archive
+        .entries()
+        .map(|(name, data)| (name.to_string(), data.to_vec()))
+        .collect();
+
+    let crate_json = match archive.crate_json() {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+
+    // the crate.json `entrypoint`, else `app.wasm` or the only wasm of the archive
+    let wasm_entry = match crate_json.get("entrypoint").and_then(Value::as_str) {
+        Some(entrypoint) => Some(entrypoint.to_string()),
+        None if entries.contains_key("app.wasm") => Some("app.wasm".to_string()),
+        None => {
+            let mut wasm = entries.keys().filter(|name| name.ends_with(".wasm"));
+            wasm.next().filter(|_| wasm.next().is_none()).cloned()
+        }
+    };
+
+    Ok(CrateSummary {
+        entries,
+        crate_json,
+        wasm_entry,
+    })
+}
+
+fn format_value(value: Option<&Value>) -> String {
+    value.map_or_else(|| "(none)".to_string(), Value::to_string)
+}
+
+pub fn run_diff_command(a: &str, b: &str) -> Result<()> {
+    let a = read_summary(a)?;
+    let b = read_summary(b)?;
+
+    println!("entries:");
+    for (name, data) in &a.entries {
+        match b.entries.get(name) {
+            None => println!("  - {name} ({} bytes)", data.len()),
+            Some(new_data) if new_data != data => {
+                println!("  ~ {name} ({} -> {} bytes)", data.len(), new_data.len());
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, data) in &b.entries {
+        if !a.entries.contains_key(name) {
+            println!("  + {name} ({} bytes)", data.len());
+        }
+    }
+
+    let wasm = |summary: &CrateSummary| {
+        summary
+            .wasm_entry
+            .as_ref()
+            .and_then(|entry| summary.entries.get(entry))
+            .map(Vec::len)
+    };
+    if let (Some(old), Some(new)) = (wasm(&a), wasm(&b)) {
+        println!(
+            "wasm size: {old} -> {new} bytes ({:+})",
+            new.cast_signed() - old.cast_signed()
+        );
+    }
+
+    println!("crate.json:");
+    let mut keys = a
+        .crate_json
+        .keys()
+        .chain(b.crate_json.keys())
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (old, new) = (a.crate_json.get(key), b.crate_json.get(key));
+        if old != new {
+            println!("  {key}: {} -> {}", format_value(old), format_value(new));
+        }
+    }
+
+    Ok(())
+}
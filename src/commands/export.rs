@@ -0,0 +1,75 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use log::info;
+
+use crate::{archive::CrateArchive, utils::determine_path};
+
+/// Writes a single entry to `out`, or to stdout when no output is given.
+fn extract_entry(archive: &CrateArchive, entry: &str, out: Option<String>) -> Result<()> {
+    let Result::Ok(data) = archive.read_entry(entry) else {
+        bail!(
+            "no `{entry}` entry, available entries: {}",
+            archive
+                .entries()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+
+    let Some(out) = out else {
+        io::stdout().write_all(data)?;
+        return Ok(());
+    };
+
+    let mut out = PathBuf::from(out);
+    if out.is_dir() {
+        out = out.join(
+            Path::new(entry)
+                .file_name()
+                .ok_or_else(|| anyhow!("failed to get filename of `{entry}`"))?,
+        );
+    }
+
+    fs::write(&out, data)?;
+    info!("extracted {entry} to {out:?}");
+
+    Ok(())
+}
+
+pub fn run_export_command(
+    crate_path: &str,
+    extract: Option<&str>,
+    out: Option<String>,
+) -> Result<()> {
+    let archive = CrateArchive::open(Path::new(crate_path))?;
+    if let Some(entry) = extract {
+        return extract_entry(&archive, entry, out);
+    }
+
+    let entrypoint = archive
+        .crate_json()
+        .ok()
+        .and_then(|json| json["entrypoint"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "app.wasm".to_string());
+    let wasm = archive.read_entry(&entrypoint)?;
+
+    let mut out = determine_path(out, env::current_dir()?);
+    if out.is_dir() {
+        let stem = Path::new(crate_path)
+            .file_stem()
+            .ok_or_else(|| anyhow!("failed to get crate filename"))?;
+        // not `with_extension`, versioned stems like `my-game-1.2.3` contain dots
+        out = out.join(format!("{}.wasm", stem.to_string_lossy()));
+    }
+
+    fs::write(&out, wasm)?;
+    info!("exported {entrypoint} to {out:?}");
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use semver::Version;
+
+use crate::{
+    archive::CrateArchive,
+    schema::crate_version,
+    utils::{determine_path, get_gooseboy_crates_folder, installed_crates},
+};
+
+pub(crate) fn read_name_and_version(crate_path: &Path) -> Result<(String, Version)> {
+    let crate_json = CrateArchive::open(crate_path)?.crate_json()?;
+
+    let name = crate_json["name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("crate.json: `name` must be a string"))?
+        .to_string();
+    // packing stamps the package version, so only crates packed before that lack one
+    let version = crate_version(&crate_json, || {
+        Err(anyhow!(
+            "crate.json: `version` is not a version string, repack the crate"
+        ))
+    })?;
+
+    Ok((name, version))
+}
+
+pub fn run_gc_command(keep: usize, dry_run: bool, path: Option<String>) -> Result<()> {
+    let folder = determine_path(path, get_gooseboy_crates_folder()?);
+    // crates of different targets are kept apart, e.g. `<target>/` subfolders
+    let mut crates: HashMap<(String, String), Vec<(Version, PathBuf)>> = HashMap::new();
+
+    for (crate_path, target) in installed_crates(&folder)? {
+        match read_name_and_version(&crate_path) {
+            Ok((name, version)) => crates
+                .entry((name, target))
+                .or_default()
+                .push((version, crate_path)),
+            Err(e) => warn!("skipping {crate_path:?}: {e}"),
+        }
+    }
+
+    let mut removed = 0;
+    for ((name, _), mut versions) in crates {
+        versions.sort_by(|a, b| b.0.cmp(&a.0));
+
+        // `keep` counts versions, several files of one version are kept or removed together
+        let mut newest = versions
+            .iter()
+            .map(|(version, _)| version.clone())
+            .collect::<Vec<_>>();
+        newest.dedup();
+        newest.truncate(keep);
+        let old = versions
+            .into_iter()
+            .filter(|(version, _)| !newest.contains(version));
+
+        for (version, crate_path) in old {
+            if dry_run {
+                info!("would remove {name} {version} ({crate_path:?})");
+            } else {
+                info!("removing {name} {version} ({crate_path:?})");
+                fs::remove_file(&crate_path)?;
+            }
+            removed += 1;
+        }
+    }
+
+    if dry_run {
+        info!("{removed} crate(s) would be removed");
+    } else {
+        info!("removed {removed} crate(s)");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::archive::CrateArchive;
+
+pub fn run_info_command(crate_path: &str) -> Result<()> {
+    let archive = CrateArchive::open(Path::new(crate_path))?;
+
+    if let Ok(crate_json) = archive.crate_json() {
+        for field in ["name", "description", "version"] {
+            if let Some(value) = crate_json.get(field) {
+                println!(
+                    "{field}: {}",
+                    value
+                        .as_str()
+                        .map_or_else(|| value.to_string(), str::to_string)
+                );
+            }
+        }
+    }
+
+    println!("entries:");
+    for (name, data) in archive.entries() {
+        println!("  {name} ({} bytes)", data.len());
+    }
+
+    let comment = archive.comment();
+    if !comment.is_empty() {
+        println!("provenance:");
+        for line in comment.lines() {
+            println!("  {line}");
+        }
+    }
+
+    Ok(())
+}
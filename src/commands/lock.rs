@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use log::{info, warn};
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, value};
+
+use crate::{
+    commands::gc::read_name_and_version,
+    utils::{
+        TARGET, determine_path, get_gooseboy_crates_folder, installed_crates, is_target_folder,
+        sha256_file,
+    },
+};
+
+pub const DEFAULT_LOCKFILE: &str = "gooseboy.lock";
+
+struct LockEntry {
+    name: String,
+    version: String,
+    checksum: String,
+    file: PathBuf,
+    /// Crates of other targets live in a `<target>/` subfolder of the crates folder
+    target: String,
+}
+
+fn read_crates(folder: &Path) -> Result<Vec<LockEntry>> {
+    let mut crates = Vec::new();
+
+    for (file, target) in installed_crates(folder)? {
+        match read_name_and_version(&file) {
+            Ok((name, version)) => crates.push(LockEntry {
+                name,
+                version: version.to_string(),
+                checksum: sha256_file(&file)?,
+                file,
+                target,
+            }),
+            Err(e) => warn!("skipping {file:?}: {e}"),
+        }
+    }
+
+    crates.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+    Ok(crates)
+}
+
+/// Lockfile paths are joined onto the crates folder, so only a bare file name is accepted.
+fn locked_file_name(lockfile: &Path, file: String) -> Result<PathBuf> {
+    let path = PathBuf::from(&file);
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(path),
+        _ => bail!("{lockfile:?}: `file` must be a plain file name, got {file:?}"),
+    }
+}
+
+fn read_lockfile(lockfile: &Path) -> Result<Vec<LockEntry>> {
+    let doc = fs::read_to_string(lockfile)
+        .map_err(|e| anyhow!("failed to read {lockfile:?}: {e}"))?
+        .parse::<DocumentMut>()?;
+
+    let Some(tables) = doc.get("crate").and_then(Item::as_array_of_tables) else {
+        return Ok(Vec::new());
+    };
+
+    tables
+        .iter()
+        .map(|table| {
+            let field = |key: &str| {
+                table
+                    .get(key)
+                    .and_then(Item::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow!("{lockfile:?}: crate entry is missing `{key}`"))
+            };
+
+            let target = match table.get("target") {
+                Some(target) => target
+                    .as_str()
+                    .filter(|t| is_target_folder(t) && !t.contains(['/', '\\']))
+                    .ok_or_else(|| anyhow!("{lockfile:?}: `target` must be a wasm target triple"))?
+                    .to_string(),
+                None => TARGET.to_string(),
+            };
+
+            Ok(LockEntry {
+                name: field("name")?,
+                version: field("version")?,
+                checksum: field("checksum")?,
+                file: locked_file_name(lockfile, field("file")?)?,
+                target,
+            })
+        })
+        .collect()
+}
+
+pub fn run_freeze_command(lockfile: &Path, path: Option<String>) -> Result<()> {
+    let folder = determine_path(path, get_gooseboy_crates_folder()?);
+    let crates = read_crates(&folder)?;
+
+    let mut tables = ArrayOfTables::new();
+    for entry in &crates {
+        let mut table = Table::new();
+        table["name"] = value(&entry.name);
+        table["version"] = value(&entry.version);
+        table["checksum"] = value(&entry.checksum);
+        table["file"] = value(
+            entry
+                .file
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        );
+        if entry.target != TARGET {
+            table["target"] = value(&entry.target);
+        }
+        tables.push(table);
+    }
+
+    let mut doc = DocumentMut::new();
+    doc.insert("crate", Item::ArrayOfTables(tables));
+    fs::write(lockfile, doc.to_string())?;
+
+    info!("locked {} crate(s) to {lockfile:?}", crates.len());
+    Ok(())
+}
+
+pub fn run_sync_command(
+    lockfile: &Path,
+    from: &[PathBuf],
+    dry_run: bool,
+    path: Option<String>,
+) -> Result<()> {
+    let folder = determine_path(path, get_gooseboy_crates_folder()?);
+    let locked = read_lockfile(lockfile)?;
+    let installed = read_crates(&folder)?;
+
+    for entry in &installed {
+        if locked.iter().any(|l| l.checksum == entry.checksum) {
+            continue;
+        }
+
+        info!(
+            "removing {} {} ({:?})",
+            entry.name, entry.version, entry.file
+        );
+        if !dry_run {
+            fs::remove_file(&entry.file)?;
+        }
+    }
+
+    let mut available: HashMap<String, PathBuf> = HashMap::new();
+    for dir in from {
+        for entry in read_crates(dir)? {
+            available.entry(entry.checksum).or_insert(entry.file);
+        }
+    }
+
+    let mut missing = Vec::new();
+    for entry in &locked {
+        if installed.iter().any(|i| i.checksum == entry.checksum) {
+            continue;
+        }
+
+        let Some(source) = available.get(&entry.checksum) else {
+            missing.push(format!("{} {}", entry.name, entry.version));
+            continue;
+        };
+
+        info!(
+            "installing {} {} from {source:?}",
+            entry.name, entry.version
+        );
+        if !dry_run {
+            let target_folder = if entry.target == TARGET {
+                folder.clone()
+            } else {
+                folder.join(&entry.target)
+            };
+            fs::create_dir_all(&target_folder)?;
+            fs::copy(source, target_folder.join(&entry.file))?;
+        }
+    }
+
+    if !missing.is_empty() {
+        bail!(
+            "no crate matching the locked checksum was found for: {}",
+            missing.join(", ")
+        );
+    }
+
+    Ok(())
+}
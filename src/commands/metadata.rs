@@ -0,0 +1,87 @@
+use anyhow::{Result, bail};
+use serde_json::{Value, json};
+
+use crate::utils::{
+    TARGET, get_cargo_metadata, get_package, get_target_directory, resolve_path_and_package,
+    resolve_project_dir,
+};
+
+pub const METADATA_FORMAT_VERSION: u32 = 1;
+
+/// The direct dependencies of `package` other than dev-dependencies, with the versions
+/// cargo resolved for them.
+fn resolved_dependencies(package: &Value, metadata: &Value) -> Vec<Value> {
+    let node = metadata["resolve"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|node| node["id"] == package["id"]);
+    let deps = node
+        .and_then(|node| node["deps"].as_array())
+        .into_iter()
+        .flatten()
+        .filter(|dep| {
+            dep["dep_kinds"]
+                .as_array()
+                .is_none_or(|kinds| kinds.iter().any(|kind| kind["kind"] != "dev"))
+        });
+
+    deps.filter_map(|dep| {
+        metadata["packages"]
+            .as_array()?
+            .iter()
+            .find(|p| p["id"] == dep["pkg"])
+    })
+    .map(|dep| {
+        json!({
+            "name": dep["name"],
+            "version": dep["version"],
+            "source": dep["source"],
+        })
+    })
+    .collect()
+}
+
+pub fn run_metadata_command(
+    package: Option<String>,
+    format_version: u32,
+    include_deps: bool,
+) -> Result<()> {
+    if format_version != METADATA_FORMAT_VERSION {
+        bail!(
+            "unsupported metadata format version {format_version}, only {METADATA_FORMAT_VERSION} is available"
+        );
+    }
+
+    let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
+    let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
+    let metadata = get_cargo_metadata(&path, include_deps)?;
+    let package = get_package(&path, &metadata)?;
+
+    let crate_types = package["targets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|target| target["crate_types"].as_array().into_iter().flatten())
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut output = json!({
+        "format_version": METADATA_FORMAT_VERSION,
+        "name": package["name"],
+        "version": package["version"],
+        "crate_types": crate_types,
+        "target": TARGET,
+        "target_directory": get_target_directory(&metadata),
+        "manifest_path": package["manifest_path"],
+        "gooseboy": package["metadata"]["gooseboy"].clone(),
+    });
+
+    if include_deps {
+        output["dependencies"] = json!(resolved_dependencies(package, &metadata));
+    }
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    Ok(())
+}
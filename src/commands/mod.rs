@@ -1,3 +1,18 @@
+pub mod audit;
 pub mod build;
+pub mod check;
+pub mod diff;
+pub mod export;
+pub mod gc;
+pub mod info;
+pub mod lock;
+pub mod metadata;
 pub mod new;
 pub mod pack;
+pub mod registry;
+pub mod relocate;
+pub mod schema;
+pub mod size_tree;
+pub mod test;
+pub mod verify;
+pub mod wasm_path;
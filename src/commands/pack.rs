@@ -1,67 +1,1888 @@
 use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, HashSet, btree_map::Entry},
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{Ok, Result};
-use log::trace;
-use zip::write::SimpleFileOptions;
+use anyhow::{Ok, Result, anyhow, bail};
+use clap::{Args, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info, trace, warn};
+use rayon::prelude::*;
+use regex::Regex;
+use semver::{Version, VersionReq};
+use serde_json::{Value, json};
+use toml_edit::{DocumentMut, Item, Table};
+use walkdir::WalkDir;
 
 use crate::{
-    commands::build::build_project,
+    archive::{
+        ArchiveFormat, ArchiveWriter, CHECKSUMS_ENTRY, ChecksumAlgo, CrateArchive,
+        EntryCompression, sanitize_entry_name,
+    },
+    commands::{
+        build::{BuildArgs, MessageFormat, WasmArtifact, build_package, build_project},
+        gc::read_name_and_version,
+        verify::verify_crate,
+    },
+    config::{ConfigFormat, compression_overrides, read_config},
+    schema::{check_schema_version, crate_version, lint_crate_json, stamp_schema_version},
     utils::{
-        copy_crate, determine_path, get_cargo_metadata, get_gooseboy_crates_folder,
-        get_project_name, get_wasm_path, resolve_path_and_package, resolve_project_dir,
+        DEFAULT_NAME_PATTERN, copy_crate, crate_json_path, crate_temp_file, depends_on,
+        determine_path, get_cargo_metadata, get_default_members, get_package, get_project_name,
+        get_project_version, get_target_crates_folder, get_target_directory, get_wasm_path,
+        get_workspace_members, link_latest, parse_ssh_destination, persist_crate, read_crate_json,
+        resolve_path_and_package, resolve_project_dir, run_command, run_command_output,
+        sha256_file, target_crates_path, upload_crate, validate_crate_name, workspace_resolver,
+    },
+    wasm::{
+        Signature, append_custom_section, export_signature, export_signatures, has_unwinding,
+        read_export_details, read_exports, read_function_exports, read_imports, required_features,
+        strip_custom_section, validate_wasm,
     },
 };
 
-pub fn pack_crate(path: &PathBuf, release: bool) -> Result<PathBuf> {
-    let metadata = get_cargo_metadata(path)?;
-    let (_filename, mut src) = get_wasm_path(path, release, &metadata)?;
-    let wasm_src = src.clone();
+pub const MAX_ICON_SIZE: u32 = 512;
+pub const DEFAULT_MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+/// Host ABI version crates are checked against unless `--host-abi-version` is given.
+pub const HOST_ABI_VERSION: &str = "1.0.0";
+/// Custom section `--emit-metadata-section` embeds crate.json in.
+pub const METADATA_SECTION: &str = "gooseboy.metadata";
+/// Capabilities gooseboy hosts can grant, crates request them in the crate.json
+/// `capabilities`.
+pub const HOST_CAPABILITIES: [&str; 6] = [
+    "filesystem",
+    "network",
+    "audio",
+    "storage",
+    "clipboard",
+    "gamepad",
+];
+pub const DEFAULT_NAME_TEMPLATE: &str = "{name}.gbcrate";
+const ALL_TARGETS_NAME_TEMPLATE: &str = "{name}-{target}.gbcrate";
+const REQUIRED_ENTRIES: [&str; 2] = ["app.wasm", "crate.json"];
+pub const DEPENDENCIES_ENTRY: &str = "dependencies.json";
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingCrateJson {
+    /// Fail, so a crate is never packed with metadata nobody wrote
+    #[default]
+    Error,
+    /// Generate a minimal crate.json from the package's Cargo.toml
+    Generate,
+}
+
+/// Exports every gooseboy host calls, crates can declare more in the crate.json `host_exports`.
+const HOST_ENTRY_POINTS: [&str; 2] = ["main", "update"];
+
+const NAME_TEMPLATE_PLACEHOLDERS: [&str; 5] = ["name", "version", "profile", "target", "commit"];
+
+#[derive(Args)]
+pub struct PackArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+    #[arg(long)]
+    pub no_copy: bool,
+    #[arg(long, default_value_t = DEFAULT_MAX_ARCHIVE_SIZE)]
+    pub max_archive_size: u64,
+    #[arg(long)]
+    pub crate_profile: Option<String>,
+    /// File listing the permitted `module::name` imports, one per line
+    #[arg(long)]
+    pub allowed_imports: Option<PathBuf>,
+    /// Filename of the packed crate, supports {name}, {version}, {profile}, {target} and {commit}
+    #[arg(long, alias = "output-name-template", default_value = DEFAULT_NAME_TEMPLATE, value_parser = parse_name_template)]
+    pub name_template: String,
+    /// Regex the crate name has to match to be accepted by the host
+    #[arg(long, default_value = DEFAULT_NAME_PATTERN)]
+    pub name_pattern: Regex,
+    /// How deep to walk directories listed in the crate.json `assets`, unlimited by default
+    #[arg(long)]
+    pub asset_depth: Option<usize>,
+    /// Archive format of the packed crate, zip unless a tool downstream needs a tarball
+    #[arg(long, value_enum, default_value_t)]
+    pub archive_format: ArchiveFormat,
+    /// Run the `wasm-bindgen` CLI on crates depending on wasm-bindgen before packing
+    #[arg(long)]
+    pub bindgen: bool,
+    /// Don't show a progress bar while writing the archive
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Log every archive entry and its size as it is packed
+    #[arg(long)]
+    pub verbose_zip: bool,
+    /// Print the packages, targets, wasm and crate paths and destinations a pack would use,
+    /// then exit without building anything
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "table", conflicts_with = "print_config")]
+    pub plan: Option<ConfigFormat>,
+    /// Print the effective pack settings and where each one came from, then exit
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "table")]
+    pub print_config: Option<ConfigFormat>,
+    /// Also point `<name>-latest.gbcrate` at the installed crate, for hosts loading a fixed path
+    #[arg(long)]
+    pub copy_latest_symlink: bool,
+    /// Report the post-MVP wasm proposals the module uses and record them as
+    /// `wasm_features` in the packed crate.json
+    #[arg(long)]
+    pub wasm_features_report: bool,
+    /// Keep the temporary archive of a failed pack around for debugging
+    #[arg(long, alias = "keep-build-output")]
+    pub keep_temp: bool,
+    /// Record a checksum of every entry in `checksums.txt`, sha256 unless given
+    #[arg(long, value_enum, value_name = "ALGO", num_args = 0..=1, default_missing_value = "sha256")]
+    pub checksum_algo: Option<ChecksumAlgo>,
+    /// Build and pack for every one of these target triples, e.g.
+    /// `wasm32-unknown-unknown,wasm32-wasip1`
+    #[arg(long, value_name = "TRIPLES", value_delimiter = ',', value_parser = parse_wasm_target, conflicts_with_all = ["pipe", "wasm"])]
+    pub all_targets: Vec<String>,
+    /// Write the archive to stdout instead of a file, same as `--dest -`
+    #[arg(long, conflicts_with_all = ["hash_only", "workspace", "packages_from", "build_message_format"])]
+    pub pipe: bool,
+    /// Print the SHA-256 of the packed crate to stdout instead of copying it
+    #[arg(long)]
+    pub hash_only: bool,
+    /// With `--hash-only`, hash the entries of the crate instead of the archive bytes so
+    /// crates with the same contents hash the same regardless of format or compression
+    #[arg(long, requires = "hash_only")]
+    pub deterministic_checksums: bool,
+    /// Don't build, pack the wasm left by a previous build
+    #[arg(long, conflicts_with_all = ["build_only_if_changed", "per_package_build"])]
+    pub no_build: bool,
+    /// Pack this wasm, e.g. one optimized by `wasm-opt`, instead of the one cargo built
+    #[arg(long, value_name = "PATH", requires = "no_build", conflicts_with_all = ["workspace", "packages_from"])]
+    pub wasm: Option<PathBuf>,
+    /// Skip the build when the wasm is newer than `src/`, `Cargo.toml`, `Cargo.lock` and
+    /// crate.json
+    #[arg(long)]
+    pub build_only_if_changed: bool,
+    /// Pack the package's `license-file` and `LICENSE*`/`COPYING*` files under `licenses/`
+    #[arg(long)]
+    pub include_license: bool,
+    /// List exported functions the host never calls, which could be made non-public to
+    /// shrink the wasm
+    #[arg(long)]
+    pub report_unused_exports: bool,
+    /// Host ABI version the crate.json `host_abi` requirement has to accept
+    #[arg(long, default_value = HOST_ABI_VERSION)]
+    pub host_abi_version: Version,
+    /// Remove the `producers` custom section, which records the exact toolchain versions
+    /// the wasm was built with
+    #[arg(long)]
+    pub strip_producers: bool,
+    /// Also write the pack result (package, paths, sizes and SHA-256) as JSON to this file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["workspace", "packages_from", "all_targets", "pipe"])]
+    pub result_json: Option<PathBuf>,
+    /// Compare the exports of the new wasm with those of this previously packed crate and
+    /// report removed or changed ones as breaking
+    #[arg(long, value_name = "OLD_CRATE")]
+    pub compat_check: Option<PathBuf>,
+    /// Fail the pack when `--compat-check` finds breaking changes
+    #[arg(long, requires = "compat_check")]
+    pub deny_breaking: bool,
+    /// Store every entry under this folder inside the archive, e.g. `payload`, for hosts
+    /// expecting a directory layout
+    #[arg(long, value_name = "DIR", value_parser = parse_archive_prefix)]
+    pub archive_prefix: Option<String>,
+    /// Capabilities the host can grant, out of the ones gooseboy knows, anything else in the
+    /// crate.json `capabilities` is rejected
+    #[arg(long, value_name = "CAPABILITIES", value_delimiter = ',', default_values = HOST_CAPABILITIES, value_parser = parse_host_capability)]
+    pub host_capabilities: Vec<String>,
+    /// Also embed crate.json in a `gooseboy.metadata` custom section of the wasm, for hosts
+    /// loading the bare module
+    #[arg(long)]
+    pub emit_metadata_section: bool,
+    /// Abort the pack, killing cargo if it is still building, once it runs longer than this
+    /// many seconds in total
+    #[arg(long, value_name = "SECONDS", conflicts_with = "build_retries")]
+    pub time_limit: Option<u64>,
+    /// Print the exports of the wasm as a JSON array sorted by name, with their kind and, for
+    /// functions, their signature
+    #[arg(long, conflicts_with_all = ["pipe", "hash_only", "workspace", "packages_from", "all_targets", "build_message_format"])]
+    pub dump_exports: bool,
+    /// Write the archive in this directory and move it next to the wasm once it's complete,
+    /// e.g. when the target directory is on a slow network file system
+    #[arg(long, value_name = "DIR")]
+    pub staging_dir: Option<PathBuf>,
+    /// What to do when the package has no crate.json
+    #[arg(long, value_enum, default_value_t)]
+    pub on_missing_crate_json: MissingCrateJson,
+    /// Record the exact versions of every dependency resolved in `Cargo.lock` in a
+    /// `dependencies.json` entry
+    #[arg(long)]
+    pub dependencies_from_lock: bool,
+    /// Reopen and validate the packed crate before copying it, always on with `--ci`
+    #[arg(long)]
+    pub verify_after_pack: bool,
+    /// Leave this entry (e.g. `crate.json` or an asset) out of the archive, can be repeated
+    #[arg(long = "exclude-entry", value_name = "NAME")]
+    pub exclude_entries: Vec<String>,
+    /// Allow excluding entries gooseboy hosts require, the crate won't load on them
+    #[arg(long)]
+    pub allow_invalid: bool,
+    /// Only pack workspace members with files changed since this git ref
+    #[arg(long, requires = "workspace")]
+    pub since: Option<String>,
+    /// Build each workspace member on its own instead of in one workspace build, slower
+    /// but avoids feature unification between members
+    #[arg(long, requires = "workspace")]
+    pub per_package_build: bool,
+    /// Pack every package listed (one per line) in this file, or `-` for stdin
+    #[arg(long, conflicts_with_all = ["package", "workspace"])]
+    pub packages_from: Option<String>,
+    /// Folder to copy the crate to, or `ssh://user@host:/path` to upload it over ssh
+    #[arg(long, conflicts_with = "destination_path")]
+    pub dest: Option<String>,
+    pub package: Option<String>,
+    pub destination_path: Option<String>,
+}
+
+impl PackArgs {
+    /// Fails once `--time-limit` has run out, naming the phase that was running.
+    fn check_time_limit(&self, phase: &str) -> Result<()> {
+        if let (Some(limit), Some(deadline)) = (self.time_limit, self.build.deadline)
+            && Instant::now() >= deadline
+        {
+            bail!("the time limit of {limit}s was exceeded while {phase}");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the archive goes to stdout instead of a file, with `--pipe` or `--dest -`.
+    fn pipe(&self) -> bool {
+        self.pipe || self.destination() == Some("-")
+    }
+
+    fn destination(&self) -> Option<&str> {
+        self.dest.as_deref().or(self.destination_path.as_deref())
+    }
+}
+
+fn parse_wasm_target(target: &str) -> Result<String> {
+    if !target.starts_with("wasm32-") && !target.starts_with("wasm64-") {
+        bail!("{target:?} is not a wasm target, expected a `wasm32-*` or `wasm64-*` triple");
+    }
+    Ok(target.to_string())
+}
+
+fn parse_host_capability(capability: &str) -> Result<String> {
+    if !HOST_CAPABILITIES.contains(&capability) {
+        bail!(
+            "unknown capability {capability:?}, expected one of {}",
+            HOST_CAPABILITIES.join(", ")
+        );
+    }
+    Ok(capability.to_string())
+}
+
+fn parse_archive_prefix(prefix: &str) -> Result<String> {
+    let prefix = prefix.trim_matches('/');
+    sanitize_entry_name(prefix)?;
+    Ok(format!("{prefix}/"))
+}
+
+fn read_icon(path: &Path, crate_json: &Value) -> Result<Option<(&'static str, Vec<u8>)>> {
+    let Some(icon) = crate_json.get("icon") else {
+        return Ok(None);
+    };
+    let icon = icon
+        .as_str()
+        .ok_or_else(|| anyhow!("crate.json: `icon` must be a path string"))?;
+    let icon_path = path.join(icon);
+
+    if !icon_path.is_file() {
+        bail!("crate.json: icon {icon_path:?} does not exist");
+    }
+
+    let extension = icon_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+
+    let entry_name = match extension.as_deref() {
+        Some("png") => {
+            let (width, height) = image::image_dimensions(&icon_path)
+                .map_err(|e| anyhow!("crate.json: failed to read icon {icon_path:?}: {e}"))?;
+            if width != height {
+                bail!("crate.json: icon {icon_path:?} must be square, got {width}x{height}");
+            }
+            if width > MAX_ICON_SIZE {
+                bail!(
+                    "crate.json: icon {icon_path:?} is {width}x{height}, the maximum is {MAX_ICON_SIZE}x{MAX_ICON_SIZE}"
+                );
+            }
+            "icon.png"
+        }
+        Some("svg") => "icon.svg",
+        _ => bail!("crate.json: icon {icon_path:?} must be a PNG or SVG file"),
+    };
+
+    trace!("including icon {icon_path:?} as {entry_name}");
+    Ok(Some((entry_name, std::fs::read(icon_path)?)))
+}
+
+/// Finds the license texts of the package, its `license-file` and the `LICENSE*`,
+/// `LICENCE*` and `COPYING*` files next to its Cargo.toml, as `licenses/<file>` entries.
+fn find_licenses(path: &Path, metadata: &Value) -> Result<Vec<(String, PathBuf)>> {
+    let package = get_package(path, metadata)?;
+
+    let mut files = Vec::new();
+    if let Some(license_file) = package["license_file"].as_str() {
+        let license_file = path.join(license_file);
+        if !license_file.is_file() {
+            bail!("license-file {license_file:?} does not exist");
+        }
+        files.push(license_file);
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_ascii_uppercase();
+        if ["LICENSE", "LICENCE", "COPYING"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+            && entry.file_type()?.is_file()
+        {
+            files.push(entry.path());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|file| fs::canonicalize(file).is_ok_and(|file| seen.insert(file)));
+    files.sort();
+
+    if files.is_empty() {
+        match package["license"].as_str() {
+            Some(license) => warn!(
+                "no license file found in {path:?}, the crate declares `{license}` but won't include its text"
+            ),
+            None => warn!("no license file found in {path:?} and the package declares no license"),
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            trace!("including license {file:?}");
+            (format!("licenses/{name}"), file)
+        })
+        .collect())
+}
+
+fn read_assets(
+    path: &Path,
+    crate_json: &Value,
+    max_depth: Option<usize>,
+) -> Result<Vec<(String, PathBuf)>> {
+    let Some(assets) = crate_json.get("assets") else {
+        return Ok(Vec::new());
+    };
+    let assets = assets
+        .as_array()
+        .ok_or_else(|| anyhow!("crate.json: `assets` must be an array of paths"))?;
+
+    let root = fs::canonicalize(path)?;
+    let mut files = Vec::new();
+    for asset in assets {
+        let asset = asset
+            .as_str()
+            .ok_or_else(|| anyhow!("crate.json: `assets` must be an array of paths"))?;
+        let asset_path = fs::canonicalize(path.join(asset))
+            .map_err(|e| anyhow!("crate.json: asset {asset:?} could not be read: {e}"))?;
+
+        let mut walk = WalkDir::new(&asset_path).sort_by_file_name();
+        if let Some(depth) = max_depth {
+            walk = walk.max_depth(depth);
+        }
+
+        for entry in walk {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let name = entry
+                .path()
+                .strip_prefix(&root)
+                .map_err(|_| anyhow!("crate.json: asset {asset_path:?} is outside the crate"))?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((name, entry.into_path()));
+        }
+    }
+
+    Ok(files)
+}
+
+fn apply_crate_profile(crate_json: &mut Value, profile: Option<&str>) -> Result<()> {
+    let base = crate_json
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("crate.json: expected an object at the top level"))?;
+    let profiles = base.remove("profiles");
+
+    let Some(profile) = profile else {
+        return Ok(());
+    };
+
+    let overrides = profiles
+        .as_ref()
+        .and_then(|p| p.get(profile))
+        .ok_or_else(|| anyhow!("crate.json: profile {profile:?} not found in `profiles`"))?
+        .as_object()
+        .ok_or_else(|| anyhow!("crate.json: profile {profile:?} must be an object"))?;
+
+    trace!("applying crate.json profile {profile:?}");
+    for (key, value) in overrides {
+        base.insert(key.clone(), value.clone());
+    }
+
+    Ok(())
+}
+
+fn read_allowed_imports(
+    allowed_imports: Option<&Path>,
+    crate_json: &Value,
+) -> Result<Option<HashSet<String>>> {
+    let mut allowed = None;
+
+    if let Some(file) = allowed_imports {
+        let list = fs::read_to_string(file)
+            .map_err(|e| anyhow!("failed to read allowed imports {file:?}: {e}"))?;
+        allowed.get_or_insert_with(HashSet::new).extend(
+            list.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_string),
+        );
+    }
+
+    if let Some(list) = crate_json.get("allowed_imports") {
+        let list = list
+            .as_array()
+            .ok_or_else(|| anyhow!("crate.json: `allowed_imports` must be an array"))?;
+        for import in list {
+            let import = import
+                .as_str()
+                .ok_or_else(|| anyhow!("crate.json: `allowed_imports` must contain strings"))?;
+            allowed
+                .get_or_insert_with(HashSet::new)
+                .insert(import.to_string());
+        }
+    }
+
+    Ok(allowed)
+}
+
+fn check_imports(wasm: &[u8], allowed: &HashSet<String>) -> Result<()> {
+    let disallowed = read_imports(wasm)?
+        .into_iter()
+        .filter(|import| {
+            !allowed.contains(import)
+                && !import
+                    .split_once("::")
+                    .is_some_and(|(module, _)| allowed.contains(&format!("{module}::*")))
+        })
+        .collect::<Vec<_>>();
+
+    if !disallowed.is_empty() {
+        bail!(
+            "wasm imports not provided by the host:\n  {}",
+            disallowed.join("\n  ")
+        );
+    }
+
+    Ok(())
+}
+
+fn parse_name_template(template: &str) -> Result<String> {
+    let unknown = Regex::new(r"\{([^}]*)\}")?
+        .captures_iter(template)
+        .map(|c| c[1].to_string())
+        .filter(|p| !NAME_TEMPLATE_PLACEHOLDERS.contains(&p.as_str()))
+        .collect::<Vec<_>>();
+
+    if !unknown.is_empty() {
+        bail!(
+            "unknown placeholder(s) {}, expected one of {}",
+            unknown
+                .iter()
+                .map(|p| format!("{{{p}}}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            NAME_TEMPLATE_PLACEHOLDERS
+                .map(|p| format!("{{{p}}}"))
+                .join(", ")
+        );
+    }
+
+    Ok(template.to_string())
+}
+
+fn render_name_template(
+    template: &str,
+    path: &PathBuf,
+    name: &str,
+    version: &str,
+    target: &str,
+    release: bool,
+) -> Result<String> {
+    let mut filename = template
+        .replace("{name}", name)
+        .replace("{version}", version)
+        .replace("{profile}", if release { "release" } else { "debug" })
+        .replace("{target}", target);
+
+    if filename.contains("{commit}") {
+        let commit = run_command_output(path, "git", &["rev-parse", "--short", "HEAD"])
+            .map_err(|e| anyhow!("failed to resolve {{commit}} for the crate filename: {e}"))?;
+        filename = filename.replace("{commit}", commit.trim());
+    }
+
+    Ok(filename)
+}
+
+/// Renders the filename of the packed crate from its crate.json name and version.
+fn crate_filename(
+    path: &PathBuf,
+    metadata: &Value,
+    crate_json: &Value,
+    args: &PackArgs,
+) -> Result<String> {
+    let name = match crate_json.get("name") {
+        Some(name) => name
+            .as_str()
+            .ok_or_else(|| anyhow!("crate.json: `name` must be a string"))?
+            .to_string(),
+        None => get_project_name(path, metadata)?,
+    };
+    let name = validate_crate_name(&name, &args.name_pattern)?;
+
+    let version = crate_version(crate_json, || get_project_version(path, metadata))?.to_string();
+    // crates of several targets are told apart by their filename
+    let name_template =
+        if !args.all_targets.is_empty() && args.name_template == DEFAULT_NAME_TEMPLATE {
+            ALL_TARGETS_NAME_TEMPLATE
+        } else {
+            &args.name_template
+        };
+
+    render_name_template(
+        name_template,
+        path,
+        &name,
+        &version,
+        &args.build.target,
+        args.build.release,
+    )
+}
+
+fn provenance_comment(release: bool) -> String {
+    let profile = if release { "release" } else { "debug" };
+    let timestamp = jiff::Timestamp::now().strftime("%Y-%m-%dT%H:%M:%SZ");
+
+    format!(
+        "packed by cargo-gooseboy {}\nprofile: {profile}\ntimestamp: {timestamp}",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Lists the packages `Cargo.lock` resolved for the package at `path`, following the
+/// dependency graph from it and skipping its dev-dependencies.
+fn locked_dependencies(path: &Path, metadata: &Value) -> Result<Value> {
+    let lock_path =
+        Path::new(metadata["workspace_root"].as_str().unwrap_or_default()).join("Cargo.lock");
+    let lock = fs::read_to_string(&lock_path)
+        .map_err(|e| anyhow!("failed to read {lock_path:?}, build the crate first: {e}"))?
+        .parse::<DocumentMut>()
+        .map_err(|e| anyhow!("failed to parse {lock_path:?}: {e}"))?;
+
+    let field =
+        |package: &Table, key: &str| package.get(key).and_then(Item::as_str).map(str::to_string);
+    let packages = lock
+        .get("package")
+        .and_then(Item::as_array_of_tables)
+        .ok_or_else(|| anyhow!("{lock_path:?} has no packages"))?;
+    let find = |name: &str, version: Option<&str>| {
+        packages.iter().find(|package| {
+            field(package, "name").as_deref() == Some(name)
+                && version
+                    .is_none_or(|version| field(package, "version").as_deref() == Some(version))
+        })
+    };
+
+    let package = get_package(path, metadata)?;
+    let dev_only = dev_only_dependencies(package);
+
+    let root = find(
+        &get_project_name(path, metadata)?,
+        Some(&get_project_version(path, metadata)?),
+    )
+    .ok_or_else(|| anyhow!("the package is missing from {lock_path:?}, build the crate first"))?;
+    let mut queue = vec![(root, true)];
+    let mut resolved = BTreeMap::new();
+    while let Some((package, is_root)) = queue.pop() {
+        let dependencies = package
+            .get("dependencies")
+            .and_then(Item::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|dep| dep.as_str());
+        for dep in dependencies {
+            // entries are `name`, `name version` or `name version (source)`
+            let mut parts = dep.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            if is_root && dev_only.contains(name) {
+                continue;
+            }
+            let dep = find(name, parts.next())
+                .ok_or_else(|| anyhow!("{lock_path:?}: unresolved dependency {dep:?}"))?;
+
+            if let Entry::Vacant(entry) =
+                resolved.entry((field(dep, "name"), field(dep, "version")))
+            {
+                entry.insert(json!({
+                    "name": field(dep, "name"),
+                    "version": field(dep, "version"),
+                    "source": field(dep, "source"),
+                    "checksum": field(dep, "checksum"),
+                }));
+                queue.push((dep, false));
+            }
+        }
+    }
+
+    Ok(Value::Array(resolved.into_values().collect()))
+}
+
+/// Names of the packages the package only pulls in as dev-dependencies, keyed like
+/// `Cargo.lock` by package name rather than by rename.
+fn dev_only_dependencies(package: &Value) -> HashSet<&str> {
+    let dependencies = package["dependencies"].as_array().into_iter().flatten();
+    let (dev, normal): (Vec<_>, Vec<_>) = dependencies.partition(|dep| dep["kind"] == "dev");
+    let normal = normal
+        .iter()
+        .filter_map(|dep| dep["name"].as_str())
+        .collect::<HashSet<_>>();
+
+    dev.iter()
+        .filter_map(|dep| dep["name"].as_str())
+        .filter(|name| !normal.contains(name))
+        .collect()
+}
+
+struct CrateWriter {
+    archive: ArchiveWriter,
+    entries: HashSet<String>,
+    excluded: Vec<String>,
+    checksums: Option<(ChecksumAlgo, String)>,
+    prefix: String,
+    compression: HashMap<String, EntryCompression>,
+    verbose: bool,
+    progress: Option<ProgressBar>,
+}
+
+impl CrateWriter {
+    fn new(file: File, format: ArchiveFormat, excluded: Vec<String>, verbose: bool) -> Self {
+        Self {
+            archive: ArchiveWriter::new(file, format),
+            entries: HashSet::new(),
+            excluded,
+            checksums: None,
+            prefix: String::new(),
+            compression: HashMap::new(),
+            verbose,
+            progress: None,
+        }
+    }
+
+    fn with_checksums(mut self, algo: ChecksumAlgo) -> Self {
+        self.checksums = Some((algo, String::new()));
+        self
+    }
+
+    /// Stores every entry under `prefix`, checksums still list the unprefixed names.
+    fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    fn with_compression_overrides(mut self, overrides: HashMap<String, EntryCompression>) -> Self {
+        self.compression = overrides;
+        self
+    }
+
+    fn with_progress(mut self, total_bytes: u64) -> Result<Self> {
+        let progress = ProgressBar::new(total_bytes).with_style(ProgressStyle::with_template(
+            "{bar:40} {percent:>3}% {bytes}/{total_bytes}",
+        )?);
+        self.progress = Some(progress);
+        Ok(self)
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.write_reader(name, &mut &data[..], data.len() as u64)
+    }
+
+    fn write_file(&mut self, name: &str, path: &Path) -> Result<()> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        self.write_reader(name, &mut io::BufReader::new(file), size)
+    }
+
+    fn write_reader(&mut self, name: &str, reader: &mut dyn Read, size: u64) -> Result<()> {
+        if !self.entries.insert(name.to_string()) {
+            bail!("duplicate archive entry {name:?}");
+        }
+
+        if self.excluded.iter().any(|e| e == name) {
+            trace!("excluding {name}");
+            return Ok(());
+        }
+
+        if self.verbose {
+            info!("adding {name} ({size} bytes)");
+        } else {
+            trace!("adding {name} ({size} bytes)");
+        }
+
+        // checksummed entries are read into memory first so they can be hashed
+        let mut data = Vec::new();
+        let mut buffered;
+        let reader: &mut dyn Read = match &mut self.checksums {
+            Some((algo, checksums)) => {
+                reader.read_to_end(&mut data)?;
+                checksums.push_str(&algo.checksum_line(name, &data));
+                buffered = data.as_slice();
+                &mut buffered
+            }
+            None => &mut *reader,
+        };
+
+        let compression = EntryCompression::for_entry(name, &self.compression);
+        let name = format!("{}{name}", self.prefix);
+        match &self.progress {
+            Some(progress) => {
+                self.archive
+                    .write_entry(&name, &mut progress.wrap_read(reader), size, compression)
+            }
+            None => self.archive.write_entry(&name, reader, size, compression),
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(progress) = &self.progress {
+            progress.finish_and_clear();
+        }
+        if let Some((_, checksums)) = &self.checksums {
+            self.archive.write_entry(
+                &format!("{}{CHECKSUMS_ENTRY}", self.prefix),
+                &mut checksums.as_bytes(),
+                checksums.len() as u64,
+                EntryCompression::Deflated,
+            )?;
+        }
+        self.archive.finish()
+    }
+}
+
+fn read_wasm_artifact(
+    wasm_src: &Path,
+    target: &str,
+    release: bool,
+    metadata: &Value,
+) -> Result<Vec<u8>> {
+    if !wasm_src.exists() {
+        let profile = if release { "release" } else { "debug" };
+        let native_dir = get_target_directory(metadata).join(profile);
+        let stem = wasm_src
+            .file_stem()
+            .map(|s| s.to_string_lossy().replace('-', "_"))
+            .unwrap_or_default();
+
+        for native in [
+            format!("lib{stem}.so"),
+            format!("lib{stem}.dylib"),
+            format!("{stem}.dll"),
+        ] {
+            let native = native_dir.join(native);
+            if native.exists() {
+                bail!(
+                    "{wasm_src:?} not found but a native artifact exists at {native:?}, the crate was built without `--target {target}`"
+                );
+            }
+        }
+
+        bail!("{wasm_src:?} not found, run `cargo gooseboy build` first");
+    }
+
+    let buf = fs::read(wasm_src)?;
+    if !buf.starts_with(b"\0asm") {
+        bail!("{wasm_src:?} is not a wasm module, a native artifact was probably built instead");
+    }
+
+    Ok(buf)
+}
+
+fn read_or_generate_crate_json(
+    path: &Path,
+    metadata: &Value,
+    on_missing: MissingCrateJson,
+) -> Result<Vec<u8>> {
+    match crate_json_path(path)? {
+        // a crate.json5 is packed as plain JSON, without its comments
+        Some(manifest) if manifest.extension().is_some_and(|e| e == "json5") => {
+            let crate_json = read_crate_json(path)?.unwrap_or_default();
+            return Ok(serde_json::to_vec_pretty(&crate_json)?);
+        }
+        Some(manifest) => return Ok(fs::read(manifest)?),
+        None => {}
+    }
+
+    match on_missing {
+        MissingCrateJson::Error => bail!(
+            "no crate.json or crate.json5 found in {path:?}, create one or pass `--on-missing-crate-json generate` to generate it from Cargo.toml"
+        ),
+        MissingCrateJson::Generate => {
+            let name = get_project_name(path, metadata)?;
+            let description = get_package(path, metadata)?["description"]
+                .as_str()
+                .map_or_else(|| name.clone(), str::to_string);
+            let mut crate_json = json!({
+                "name": name,
+                "description": description,
+                "version": get_project_version(path, metadata)?,
+                "entrypoint": "app.wasm",
+                "permissions": [],
+            });
+            stamp_schema_version(&mut crate_json);
+            warn!("no crate.json found in {path:?}, generated one from Cargo.toml");
+
+            Ok(serde_json::to_vec_pretty(&crate_json)?)
+        }
+    }
+}
+
+/// Checks the crate.json `host_abi` requirement is well-formed and accepts `host_abi`.
+fn check_host_abi(crate_json: &Value, host_abi: &Version) -> Result<()> {
+    let Some(requirement) = crate_json.get("host_abi") else {
+        return Ok(());
+    };
+    let requirement = requirement
+        .as_str()
+        .ok_or_else(|| anyhow!("crate.json: `host_abi` must be a version requirement string"))?;
+    let requirement = VersionReq::parse(requirement)
+        .map_err(|e| anyhow!("crate.json: invalid `host_abi` requirement {requirement:?}: {e}"))?;
+
+    if !requirement.matches(host_abi) {
+        bail!(
+            "crate.json: `host_abi` requires {requirement} but the host ABI is {host_abi}, pass --host-abi-version to pack for another host"
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks every capability the crate.json requests is one the host knows, the list stays in
+/// the packed crate.json for the host to enforce at load.
+fn check_capabilities(crate_json: &Value, host_capabilities: &[String]) -> Result<()> {
+    let Some(capabilities) = crate_json.get("capabilities") else {
+        return Ok(());
+    };
+    let capabilities = capabilities
+        .as_array()
+        .and_then(|c| c.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+        .ok_or_else(|| anyhow!("crate.json: `capabilities` must be an array of strings"))?;
+
+    let unknown = capabilities
+        .into_iter()
+        .filter(|c| !host_capabilities.iter().any(|known| known == c))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!(
+            "crate.json: unknown capabilities {}, the host knows {} (pass --host-capabilities for another host)",
+            unknown.join(", "),
+            host_capabilities.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks the `main` export has the crate.json `entry_signature`, if one is declared.
+fn check_entry_signature(wasm: &[u8], crate_json: &Value) -> Result<()> {
+    let Some(expected) = crate_json.get("entry_signature") else {
+        return Ok(());
+    };
+    let expected = expected
+        .as_str()
+        .ok_or_else(|| anyhow!("crate.json: `entry_signature` must be a string"))?
+        .parse::<Signature>()
+        .map_err(|e| anyhow!("crate.json: invalid `entry_signature`: {e}"))?;
+
+    let entry = HOST_ENTRY_POINTS[0];
+    match export_signature(wasm, entry)? {
+        Some(actual) if actual == expected => Ok(()),
+        Some(actual) => bail!(
+            "`{entry}` has the signature {actual} but crate.json declares {expected} in `entry_signature`"
+        ),
+        None => bail!(
+            "crate.json declares an `entry_signature` but the wasm exports no `{entry}` function"
+        ),
+    }
+}
+
+/// Compares the exports of `wasm` with the ones of the crate at `old_crate`, a removed export
+/// or a changed function signature breaks hosts built against the old crate.
+fn check_compat(wasm: &[u8], old_crate: &Path, deny_breaking: bool) -> Result<()> {
+    let old = CrateArchive::open(old_crate)?;
+    let entrypoint = old
+        .crate_json()?
+        .get("entrypoint")
+        .and_then(Value::as_str)
+        .unwrap_or("app.wasm")
+        .to_string();
+    let old_wasm = old.read_entry(&entrypoint)?;
+
+    let (old_exports, new_exports) = (read_exports(old_wasm)?, read_exports(wasm)?);
+    let (old_functions, new_functions) = (export_signatures(old_wasm)?, export_signatures(wasm)?);
+
+    let mut breaking = 0;
+    for export in &old_exports {
+        if !new_exports.contains(export) {
+            warn!("breaking: `{export}` was removed");
+            breaking += 1;
+        } else if let (Some(old), Some(new)) =
+            (old_functions.get(export), new_functions.get(export))
+            && old != new
+        {
+            warn!("breaking: `{export}` changed from {old} to {new}");
+            breaking += 1;
+        }
+    }
+    for export in new_exports.iter().filter(|e| !old_exports.contains(e)) {
+        info!("compatible: `{export}` was added");
+    }
+
+    if breaking == 0 {
+        info!("no breaking changes to the exports of {old_crate:?}");
+    } else if deny_breaking {
+        bail!("{breaking} breaking change(s) to the exports of {old_crate:?}");
+    } else {
+        warn!("{breaking} breaking change(s) to the exports of {old_crate:?}");
+    }
+
+    Ok(())
+}
+
+fn dump_exports(wasm: &[u8]) -> Result<()> {
+    let exports = read_export_details(wasm)?
+        .into_iter()
+        .map(|export| {
+            let mut json = json!({ "name": export.name, "kind": export.kind });
+            if let Some(signature) = export.signature {
+                json["signature"] = json!(signature.to_string());
+                json["params"] = json!(signature.params);
+                json["results"] = json!(signature.results);
+            }
+            json
+        })
+        .collect::<Vec<_>>();
+
+    println!("{}", serde_json::to_string_pretty(&exports)?);
+    Ok(())
+}
+
+/// Logs the exported functions that are neither gooseboy entry points nor listed in the
+/// crate.json `host_exports`, this is advisory only.
+fn report_unused_exports(wasm: &[u8], crate_json: &Value) -> Result<()> {
+    let host_exports = crate_json
+        .get("host_exports")
+        .map(|exports| {
+            exports
+                .as_array()
+                .and_then(|exports| {
+                    exports
+                        .iter()
+                        .map(Value::as_str)
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| anyhow!("crate.json: `host_exports` must be an array of strings"))
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    let unused = read_function_exports(wasm)?
+        .into_iter()
+        .filter(|export| {
+            !HOST_ENTRY_POINTS.contains(&export.as_str())
+                && !host_exports.contains(&export.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    if unused.is_empty() {
+        info!("every exported function is called by the host");
+    } else {
+        info!(
+            "exported functions the host doesn't call: {}, remove `#[unsafe(no_mangle)]` or `pub` from them for a smaller wasm, or list them in the crate.json `host_exports`",
+            unused.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads a wasm produced outside of cargo, checking it's a valid module since nothing else
+/// vouches for it.
+fn read_external_wasm(wasm: &Path) -> Result<Vec<u8>> {
+    let buf = fs::read(wasm).map_err(|e| anyhow!("failed to read {wasm:?}: {e}"))?;
+    if !buf.starts_with(b"\0asm") {
+        bail!("{wasm:?} is not a wasm module");
+    }
+    validate_wasm(&buf).map_err(|e| anyhow!("{wasm:?}: {e}"))?;
+
+    Ok(buf)
+}
+
+fn run_wasm_bindgen(path: &PathBuf, wasm_src: &Path) -> Result<PathBuf> {
+    let out_dir = wasm_src.with_file_name("bindgen");
+    let stem = wasm_src
+        .file_stem()
+        .ok_or_else(|| anyhow!("failed to get wasm filename"))?
+        .to_string_lossy()
+        .into_owned();
+
+    run_command(
+        path,
+        "wasm-bindgen",
+        &[
+            "--target",
+            "no-modules",
+            "--no-typescript",
+            "--out-dir",
+            &out_dir.to_string_lossy(),
+            &wasm_src.to_string_lossy(),
+        ],
+    )
+    .map_err(|e| {
+        anyhow!("{e}\ninstall the CLI with `cargo install wasm-bindgen-cli` matching your wasm-bindgen version")
+    })?;
+
+    Ok(out_dir.join(format!("{stem}_bg.wasm")))
+}
+
+/// Picks the wasm cargo reported for the package at `path`, if any.
+fn find_artifact(path: &Path, artifacts: &[WasmArtifact]) -> Option<PathBuf> {
+    let manifest = fs::canonicalize(path.join("Cargo.toml")).ok()?;
+    artifacts
+        .iter()
+        .find(|artifact| fs::canonicalize(&artifact.manifest_path).is_ok_and(|m| m == manifest))
+        .map(|artifact| artifact.path.clone())
+}
+
+/// Packs the crate at `path`, `metadata` is the `cargo metadata` of its workspace so it can
+/// be fetched once for every member.
+pub fn pack_crate(
+    path: &PathBuf,
+    metadata: &Value,
+    args: &PackArgs,
+    artifacts: &[WasmArtifact],
+) -> Result<PathBuf> {
+    let mut src = match find_artifact(path, artifacts) {
+        Some(artifact) => artifact,
+        None => {
+            get_wasm_path(
+                path,
+                &args.build.target,
+                args.build.release,
+                args.build.crate_type,
+                metadata,
+            )?
+            .1
+        }
+    };
+    let mut wasm_src = args.wasm.clone().unwrap_or_else(|| src.clone());
     src.pop();
 
-    let crate_path = src.join(format!("{}.gbcrate", get_project_name(path, &metadata)?));
+    let mut buf = match &args.wasm {
+        Some(wasm) => read_external_wasm(wasm)?,
+        None => read_wasm_artifact(&wasm_src, &args.build.target, args.build.release, metadata)?,
+    };
+    // an external wasm is expected to be fully post-processed already
+    if args.wasm.is_none() && depends_on(path, metadata, "wasm-bindgen")? {
+        if args.bindgen {
+            wasm_src = run_wasm_bindgen(path, &wasm_src)?;
+            buf = fs::read(&wasm_src)?;
+        } else {
+            warn!(
+                "this crate depends on wasm-bindgen, its raw wasm usually needs post-processing, pass --bindgen to run the wasm-bindgen CLI before packing"
+            );
+        }
+    }
+
+    if args.strip_producers {
+        buf = strip_custom_section(&buf, "producers")?;
+        validate_wasm(&buf).map_err(|e| anyhow!("{wasm_src:?} without producers: {e}"))?;
+    }
+
+    if args.build.release && has_unwinding(&buf)? {
+        warn!(
+            "{wasm_src:?} contains unwinding code, set `panic = \"abort\"` in [profile.release] or pass --panic-abort for a smaller wasm"
+        );
+    }
+
+    let mut crate_json = read_or_generate_crate_json(path, metadata, args.on_missing_crate_json)?;
+    let mut crate_json_value: Value = serde_json::from_slice(&crate_json)
+        .map_err(|e| anyhow!("failed to parse crate.json: {e}"))?;
+    check_schema_version(&crate_json_value)?;
+    for diagnostic in lint_crate_json(&crate_json_value) {
+        warn!("{diagnostic}");
+    }
+    check_host_abi(&crate_json_value, &args.host_abi_version)?;
+    if args.crate_profile.is_some() || crate_json_value.get("profiles").is_some() {
+        apply_crate_profile(&mut crate_json_value, args.crate_profile.as_deref())?;
+        stamp_schema_version(&mut crate_json_value);
+        crate_json = serde_json::to_vec_pretty(&crate_json_value)?;
+    }
+    // installed crates are told apart by the crate.json version, which `new` sets to `1`
+    if !crate_json_value["version"].is_string() {
+        let version = crate_version(&crate_json_value, || get_project_version(path, metadata))?;
+        crate_json_value
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("crate.json: expected an object at the top level"))?
+            .insert("version".to_string(), json!(version.to_string()));
+        crate_json = serde_json::to_vec_pretty(&crate_json_value)?;
+    }
+    // after the profile, which may change the capabilities
+    check_capabilities(&crate_json_value, &args.host_capabilities)?;
+    let icon = read_icon(path, &crate_json_value)?;
+    let mut assets = read_assets(path, &crate_json_value, args.asset_depth)?;
+    if args.include_license {
+        assets.extend(find_licenses(path, metadata)?);
+    }
+    let allowed_imports = read_allowed_imports(args.allowed_imports.as_deref(), &crate_json_value)?;
+
+    if let Some(allowed) = &allowed_imports {
+        check_imports(&buf, allowed)?;
+    }
+
+    check_entry_signature(&buf, &crate_json_value)?;
+    if let Some(old_crate) = &args.compat_check {
+        check_compat(&buf, old_crate, args.deny_breaking)?;
+    }
+
+    if args.report_unused_exports {
+        report_unused_exports(&buf, &crate_json_value)?;
+    }
+
+    if args.dump_exports {
+        dump_exports(&buf)?;
+    }
+
+    if args.wasm_features_report {
+        let features = required_features(&buf);
+        if features.is_empty() {
+            info!("wasm features: none beyond the MVP");
+        } else {
+            info!("wasm features: {}", features.join(", "));
+        }
+
+        crate_json_value
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("crate.json: expected an object at the top level"))?
+            .insert("wasm_features".to_string(), json!(features));
+        stamp_schema_version(&mut crate_json_value);
+        crate_json = serde_json::to_vec_pretty(&crate_json_value)?;
+    }
+
+    if args.emit_metadata_section {
+        // replace the section of a wasm that was packed before
+        buf = strip_custom_section(&buf, METADATA_SECTION)?;
+        append_custom_section(&mut buf, METADATA_SECTION, &crate_json);
+    }
+
+    let crate_path = src.join(crate_filename(path, metadata, &crate_json_value, args)?);
     trace!("packing crate to {crate_path:?}, wasm file at {wasm_src:?}");
-    let file = File::create(crate_path.clone())?;
-    let mut zip = zip::ZipWriter::new(file);
+    // the archive is written to a temporary file that is removed if packing fails, and
+    // only moved into place once it's complete
+    let staging = match &args.staging_dir {
+        Some(staging) => {
+            fs::create_dir_all(staging)?;
+            staging
+        }
+        None => &src,
+    };
+    let temp = crate_temp_file(staging, args.keep_temp)?;
+    trace!("writing archive to {:?}", temp.path());
+    let mut writer = CrateWriter::new(
+        temp.reopen()?,
+        args.archive_format,
+        args.exclude_entries.clone(),
+        args.verbose_zip,
+    );
+    if let Some(algo) = args.checksum_algo {
+        writer = writer.with_checksums(algo);
+    }
+    if let Some(prefix) = &args.archive_prefix {
+        writer = writer.with_prefix(prefix.clone());
+    }
+    writer = writer.with_compression_overrides(compression_overrides(&read_config()?)?);
+    if !args.quiet && !args.build.workspace {
+        let mut total_bytes = (buf.len() + crate_json.len()) as u64;
+        total_bytes += icon.as_ref().map_or(0, |(_, icon)| icon.len() as u64);
+        for (_, asset_path) in &assets {
+            total_bytes += fs::metadata(asset_path)?.len();
+        }
+        writer = writer.with_progress(total_bytes)?;
+    }
+
+    writer
+        .archive
+        .set_comment(provenance_comment(args.build.release));
+
+    writer.write_entry("app.wasm", &buf)?;
+    writer.write_entry("crate.json", &crate_json)?;
 
-    let opts = SimpleFileOptions::default();
+    if let Some((entry_name, icon)) = icon {
+        writer.write_entry(entry_name, &icon)?;
+    }
 
-    zip.start_file("app.wasm", opts)?;
-    let mut buf = Vec::new();
-    File::open(wasm_src)?.read_to_end(&mut buf)?;
-    zip.write_all(&buf)?;
+    if args.dependencies_from_lock {
+        let dependencies = locked_dependencies(path, metadata)?;
+        writer.write_entry(
+            DEPENDENCIES_ENTRY,
+            &serde_json::to_vec_pretty(&dependencies)?,
+        )?;
+    }
 
-    zip.start_file("crate.json", opts)?;
-    buf = Vec::new();
-    File::open(path.join("crate.json"))?.read_to_end(&mut buf)?;
-    zip.write_all(&buf)?;
+    for (entry_name, asset_path) in assets {
+        writer.write_file(&entry_name, &asset_path)?;
+    }
 
-    zip.finish()?;
+    writer.finish()?;
+
+    let size = fs::metadata(temp.path())?.len();
+    let max_archive_size = args.max_archive_size;
+    if size > max_archive_size {
+        bail!(
+            "packed crate is {size} bytes, which exceeds the maximum archive size of {max_archive_size} bytes"
+        );
+    }
+    if args.pipe() {
+        io::copy(&mut temp.reopen()?, &mut io::stdout().lock())?;
+        return Ok(crate_path);
+    }
+    persist_crate(temp, &crate_path)?;
 
     Ok(crate_path)
 }
 
-pub fn run_pack_command(
-    release: bool,
-    package: Option<String>,
-    destination_path: Option<String>,
-    no_copy: bool,
-) -> Result<()> {
+fn read_package_list(source: &str) -> Result<Vec<String>> {
+    let list = if source == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(source)
+            .map_err(|e| anyhow!("failed to read package list {source:?}: {e}"))?
+    };
+
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Whether the wasm at [`get_wasm_path`] is strictly newer than every input of the build,
+/// any file whose mtime can't be read counts as a change.
+fn wasm_up_to_date(path: &Path, metadata: &Value, args: &PackArgs) -> Result<bool> {
+    let (_, wasm) = get_wasm_path(
+        path,
+        &args.build.target,
+        args.build.release,
+        args.build.crate_type,
+        metadata,
+    )?;
+    let Some(built) = modified(&wasm) else {
+        return Ok(false);
+    };
+
+    let mut inputs = vec![
+        path.join("Cargo.toml"),
+        path.join("crate.json"),
+        path.join("crate.json5"),
+    ];
+    if let Some(root) = metadata["workspace_root"].as_str() {
+        inputs.push(Path::new(root).join("Cargo.lock"));
+    }
+    for entry in WalkDir::new(path.join("src")) {
+        let Result::Ok(entry) = entry else {
+            return Ok(false);
+        };
+        inputs.push(entry.into_path());
+    }
+
+    for input in inputs.iter().filter(|input| input.exists()) {
+        match modified(input) {
+            Some(time) if time < built => {}
+            _ => {
+                trace!("{input:?} changed since {wasm:?} was built");
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+pub(crate) fn pack_package(args: &PackArgs, package: Option<String>, ci: bool) -> Result<PathBuf> {
     let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
     let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
-    build_project(&path, release)?;
+    let metadata = get_cargo_metadata(&path, false)?;
+    let artifacts = if args.no_build {
+        Vec::new()
+    } else if args.build_only_if_changed && wasm_up_to_date(&path, &metadata, args)? {
+        info!("nothing changed since the last build, skipping it");
+        Vec::new()
+    } else {
+        build_project(&path, &args.build)?
+    };
+    pack_and_copy(&path, &metadata, args, &artifacts, ci)
+}
+
+fn pack_and_copy(
+    path: &PathBuf,
+    metadata: &Value,
+    args: &PackArgs,
+    artifacts: &[WasmArtifact],
+    ci: bool,
+) -> Result<PathBuf> {
+    let packed = pack_crate(path, metadata, args, artifacts)?;
+    args.check_time_limit("packing")?;
+    if args.pipe() {
+        return Ok(packed);
+    }
+    if args.build.build_message_format == MessageFormat::Json {
+        println!(
+            "{}",
+            json!({
+                "reason": "gooseboy-pack",
+                "manifest_path": path.join("Cargo.toml"),
+                "crate": packed,
+            })
+        );
+    }
 
-    let packed = pack_crate(&path, release)?;
+    if (args.verify_after_pack || ci) && !args.allow_invalid {
+        verify_crate(&packed, &args.name_pattern)
+            .map_err(|e| anyhow!("verification of {packed:?} failed: {e}"))?;
+    }
 
-    if !no_copy {
-        copy_crate(
+    if args.hash_only {
+        let hash = if args.deterministic_checksums {
+            CrateArchive::open(&packed)?.content_hash()
+        } else {
+            sha256_file(&packed)?
+        };
+        if args.build.workspace || args.packages_from.is_some() {
+            println!("{hash}  {}", packed.display());
+        } else {
+            println!("{hash}");
+        }
+        if let Some(result_json) = &args.result_json {
+            write_pack_result(result_json, path, metadata, &packed, None)?;
+        }
+        return Ok(packed);
+    }
+
+    let mut destination = None;
+    if !args.no_copy
+        && let Some((host, remote_path)) = args.destination().and_then(parse_ssh_destination)
+    {
+        upload_crate(&packed, host, remote_path)?;
+        destination = args.destination().map(str::to_string);
+    } else if !args.no_copy {
+        let installed = copy_crate(
             &packed,
-            &determine_path(destination_path, get_gooseboy_crates_folder()?),
+            &determine_path(
+                args.destination().map(str::to_string),
+                get_target_crates_folder(&args.build.target)?,
+            ),
         )?;
+
+        if args.copy_latest_symlink {
+            let (name, _) = read_name_and_version(&installed)?;
+            link_latest(&installed, &name)?;
+        }
+        destination = Some(installed.display().to_string());
+    }
+    args.check_time_limit("copying")?;
+
+    if let Some(result_json) = &args.result_json {
+        write_pack_result(result_json, path, metadata, &packed, destination)?;
+    }
+
+    Ok(packed)
+}
+
+/// Writes the `--result-json` file, through a temporary file so a build system never reads
+/// a partial result.
+fn write_pack_result(
+    result_json: &Path,
+    path: &Path,
+    metadata: &Value,
+    packed: &Path,
+    installed: Option<String>,
+) -> Result<()> {
+    let archive = CrateArchive::open(packed)?;
+    let crate_json = archive.crate_json()?;
+    let entrypoint = crate_json
+        .get("entrypoint")
+        .and_then(Value::as_str)
+        .unwrap_or("app.wasm");
+
+    let result = json!({
+        "project": get_project_name(path, metadata)?,
+        "version": get_project_version(path, metadata)?,
+        "manifest_path": path.join("Cargo.toml"),
+        "crate": packed,
+        "installed": installed,
+        "size": fs::metadata(packed)?.len(),
+        "wasm_size": archive.read_entry(entrypoint)?.len(),
+        "sha256": sha256_file(packed)?,
+    });
+
+    let folder = match result_json.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(folder)
+        .map_err(|e| anyhow!("failed to write {result_json:?}: {e}"))?;
+    serde_json::to_writer_pretty(&mut temp, &result)?;
+    temp.persist(result_json)
+        .map_err(|e| anyhow!("failed to write {result_json:?}: {e}"))?;
+
+    Ok(())
+}
+
+fn report_results(results: Vec<(String, Result<PathBuf>)>) -> Result<()> {
+    let total = results.len();
+    let mut failed = 0;
+
+    for (package, result) in results {
+        match result {
+            Result::Ok(packed) => info!("{package}: packed {packed:?}"),
+            Err(e) => {
+                error!("{package}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    info!("packed {} of {total} packages", total - failed);
+
+    if failed > 0 {
+        bail!("{failed} package(s) failed to pack");
+    }
+
+    Ok(())
+}
+
+/// Packs the `default-members` of a virtual workspace, after building them like cargo does
+/// at the workspace root.
+fn pack_default_members(
+    args: &PackArgs,
+    path: &PathBuf,
+    members: &[PathBuf],
+    ci: bool,
+) -> Result<()> {
+    let artifacts = if args.no_build {
+        Vec::new()
+    } else {
+        build_project(path, &args.build)?
+    };
+
+    // like with --workspace, members without a crate.json aren't gooseboy crates
+    let members = members
+        .iter()
+        .filter(|member| crate_json_path(member).is_ok_and(|m| m.is_some()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let metadata = get_cargo_metadata(path, false)?;
+    report_results(pack_members(args, &members, &metadata, &artifacts, ci)?)
+}
+
+/// Packs and verifies the members concurrently, bounded by `--jobs`, sharing the workspace
+/// metadata and the artifacts of the build.
+fn pack_members(
+    args: &PackArgs,
+    members: &[PathBuf],
+    metadata: &Value,
+    artifacts: &[WasmArtifact],
+    ci: bool,
+) -> Result<Vec<(String, Result<PathBuf>)>> {
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(jobs) = args.build.jobs {
+        pool = pool.num_threads(jobs);
+    }
+
+    Ok(pool.build()?.install(|| {
+        members
+            .par_iter()
+            .map(|member| {
+                (
+                    member.display().to_string(),
+                    pack_and_copy(member, metadata, args, artifacts, ci),
+                )
+            })
+            .collect()
+    }))
+}
+
+fn changed_files(path: &PathBuf, since: &str) -> Result<Vec<PathBuf>> {
+    let root = run_command_output(path, "git", &["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(root.trim());
+
+    Ok(
+        run_command_output(path, "git", &["diff", "--name-only", since])?
+            .lines()
+            .map(|file| root.join(file))
+            .collect(),
+    )
+}
+
+/// The members with a crate.json, narrowed down to the ones changed since `--since`.
+fn workspace_members_to_pack(path: &PathBuf, metadata: &Value, args: &PackArgs) -> Vec<PathBuf> {
+    let mut members = get_workspace_members(metadata)
+        .into_iter()
+        .filter(|member| crate_json_path(member).is_ok_and(|m| m.is_some()))
+        .collect::<Vec<_>>();
+
+    if let Some(since) = &args.since {
+        match changed_files(path, since) {
+            Result::Ok(changed) => {
+                members.retain(|member| changed.iter().any(|file| file.starts_with(member)));
+                info!("{} member(s) changed since {since}", members.len());
+            }
+            Err(e) => warn!("failed to get changes since {since}, packing everything: {e}"),
+        }
+    }
+
+    members
+}
+
+/// Warns when a resolver 1 workspace is built as a whole while members pick their own
+/// features, since cargo then unifies the features of shared dependencies, including
+/// build and dev ones, and a member's wasm can change depending on its neighbours.
+fn warn_feature_unification(metadata: &Value, members: &[PathBuf]) -> Result<()> {
+    if workspace_resolver(metadata)? != "1" {
+        return Ok(());
+    }
+
+    let with_features = members
+        .iter()
+        .filter(|member| {
+            read_crate_json(member).is_ok_and(|crate_json| {
+                crate_json.is_some_and(|c| {
+                    c.get("features").is_some() || c.get("default_features").is_some()
+                })
+            })
+        })
+        .map(|member| get_project_name(member, metadata))
+        .collect::<Result<Vec<_>>>()?;
+    if !with_features.is_empty() {
+        warn!(
+            "the workspace uses feature resolver 1 and members {} set their own features, which cargo unifies across the workspace build, set `resolver = \"2\"` in [workspace] or pass --per-package-build",
+            with_features.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+fn pack_workspace(args: &PackArgs, ci: bool) -> Result<()> {
+    let (path, _) = resolve_path_and_package(None)?;
+    let mut artifacts = Vec::new();
+    if !args.per_package_build && !args.no_build {
+        artifacts = build_project(&path, &args.build)?;
+    }
+
+    let metadata = get_cargo_metadata(&path, false)?;
+    let mut members = workspace_members_to_pack(&path, &metadata, args);
+    if !args.per_package_build {
+        warn_feature_unification(&metadata, &members)?;
+    }
+
+    let mut results = Vec::new();
+    if args.per_package_build {
+        members.retain(|member| {
+            match get_project_name(member, &metadata)
+                .and_then(|name| build_package(&path, &args.build, Some(&name)))
+            {
+                Result::Ok(built) => {
+                    artifacts.extend(built);
+                    true
+                }
+                Err(e) => {
+                    results.push((member.display().to_string(), Err(e)));
+                    false
+                }
+            }
+        });
+    }
+
+    results.extend(pack_members(args, &members, &metadata, &artifacts, ci)?);
+
+    report_results(results)
+}
+
+/// The package directories [`pack_selected`] would pack.
+fn planned_packages(args: &PackArgs, packages: Option<&[String]>) -> Result<Vec<PathBuf>> {
+    let (cwd, _) = resolve_path_and_package(None)?;
+    if args.build.workspace {
+        let metadata = get_cargo_metadata(&cwd, false)?;
+        return Ok(workspace_members_to_pack(&cwd, &metadata, args));
+    }
+
+    let packages = match packages {
+        Some(packages) => packages.iter().cloned().map(Some).collect(),
+        None if args.package.is_none()
+            && let Some(members) = get_default_members(&cwd)? =>
+        {
+            return Ok(members
+                .into_iter()
+                .filter(|member| crate_json_path(member).is_ok_and(|m| m.is_some()))
+                .collect());
+        }
+        None => vec![args.package.clone()],
+    };
+
+    packages
+        .into_iter()
+        .map(|package| {
+            let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
+            resolve_project_dir(&path_arg, package_name_opt.as_deref())
+        })
+        .collect()
+}
+
+/// Where the packed crate ends up, `None` when it isn't copied anywhere.
+fn planned_destination(args: &PackArgs) -> Result<Option<String>> {
+    if args.pipe() {
+        return Ok(Some("stdout".to_string()));
+    }
+    if args.hash_only || args.no_copy {
+        return Ok(None);
+    }
+    if let Some(destination) = args.destination()
+        && parse_ssh_destination(destination).is_some()
+    {
+        return Ok(Some(destination.to_string()));
+    }
+
+    let folder = determine_path(
+        args.destination().map(str::to_string),
+        target_crates_path(&args.build.target)?,
+    );
+    Ok(Some(folder.display().to_string()))
+}
+
+fn print_plan(args: &mut PackArgs, format: ConfigFormat) -> Result<()> {
+    let targets = if args.all_targets.is_empty() {
+        vec![args.build.target.clone()]
+    } else {
+        args.all_targets.clone()
+    };
+    let packages = args
+        .packages_from
+        .as_deref()
+        .map(read_package_list)
+        .transpose()?;
+    let profile = if args.build.release {
+        "release"
+    } else {
+        "debug"
+    };
+    let build = if args.no_build {
+        "skipped"
+    } else if args.per_package_build {
+        "per package"
+    } else if args.build_only_if_changed {
+        "if changed"
+    } else {
+        "once"
+    };
+
+    let mut plan = Vec::new();
+    for target in targets {
+        args.build.target.clone_from(&target);
+        let destination = planned_destination(args)?;
+
+        let mut crates = Vec::new();
+        for path in planned_packages(args, packages.as_deref())? {
+            let metadata = get_cargo_metadata(&path, false)?;
+            let (_, built) = get_wasm_path(
+                &path,
+                &target,
+                args.build.release,
+                args.build.crate_type,
+                &metadata,
+            )?;
+            let crate_json = read_crate_json(&path)?.unwrap_or_else(|| json!({}));
+            let filename = crate_filename(&path, &metadata, &crate_json, args)?;
+
+            crates.push(json!({
+                "package": get_project_name(&path, &metadata)?,
+                "manifest_dir": path,
+                "wasm": args.wasm.clone().unwrap_or_else(|| built.clone()),
+                "crate": built.with_file_name(filename),
+                "destination": destination,
+            }));
+        }
+
+        plan.push(json!({
+            "target": target,
+            "profile": profile,
+            "build": build,
+            "crates": crates,
+        }));
+    }
+
+    match format {
+        ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&plan)?),
+        ConfigFormat::Table => {
+            for target in &plan {
+                println!(
+                    "{} ({}), build: {}",
+                    target["target"].as_str().unwrap_or_default(),
+                    target["profile"].as_str().unwrap_or_default(),
+                    target["build"].as_str().unwrap_or_default()
+                );
+                for planned in target["crates"].as_array().into_iter().flatten() {
+                    println!("  {}", planned["package"].as_str().unwrap_or_default());
+                    for field in ["wasm", "crate", "destination"] {
+                        println!("    {field:11}  {}", planned[field].as_str().unwrap_or("-"));
+                    }
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+pub fn run_pack_command(mut args: PackArgs, ci: bool) -> Result<()> {
+    if !args.allow_invalid
+        && let Some(entry) = args
+            .exclude_entries
+            .iter()
+            .find(|e| REQUIRED_ENTRIES.contains(&e.as_str()))
+    {
+        bail!(
+            "excluding `{entry}` makes the crate invalid for gooseboy hosts, pass --allow-invalid to do it anyway"
+        );
+    }
+
+    if let Some(format) = args.plan {
+        return print_plan(&mut args, format);
+    }
+
+    if let Some(limit) = args.time_limit {
+        args.build.deadline = Some(Instant::now() + Duration::from_secs(limit));
+    }
+
+    // stdin can only be read once, so the list is shared by every target
+    let packages = args
+        .packages_from
+        .as_deref()
+        .map(read_package_list)
+        .transpose()?;
+    if args.all_targets.is_empty() {
+        return pack_selected(&args, packages.as_deref(), ci);
+    }
+
+    let targets = args.all_targets.clone();
+    let mut failed = 0;
+    for target in &targets {
+        info!("packing for {target}");
+        args.build.target.clone_from(target);
+        if let Err(e) = pack_selected(&args, packages.as_deref(), ci) {
+            error!("{target}: {e}");
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} target(s) failed to pack", targets.len());
+    }
+
+    Ok(())
+}
+
+/// Packs the package, workspace members or package list selected by the arguments,
+/// `packages` is the list read from `--packages-from`.
+fn pack_selected(args: &PackArgs, packages: Option<&[String]>, ci: bool) -> Result<()> {
+    if args.build.workspace {
+        return pack_workspace(args, ci);
+    }
+
+    if args.package.is_none()
+        && args.packages_from.is_none()
+        && let (path, _) = resolve_path_and_package(None)?
+        && let Some(members) = get_default_members(&path)?
+    {
+        let single_package_flags = [
+            ("--result-json", args.result_json.is_some()),
+            ("--pipe", args.pipe),
+            ("--dump-exports", args.dump_exports),
+            ("--wasm", args.wasm.is_some()),
+        ];
+        if let Some((flag, _)) = single_package_flags.iter().find(|(_, set)| *set) {
+            bail!("{flag} needs a single package, pass the one to pack");
+        }
+        return pack_default_members(args, &path, &members, ci);
+    }
+
+    let Some(packages) = packages else {
+        let packed = pack_package(args, args.package.clone(), ci)?;
+        if !args.all_targets.is_empty() {
+            info!("{}: packed {packed:?}", args.build.target);
+        }
+        return Ok(());
+    };
+
+    let results = packages
+        .iter()
+        .map(|package| {
+            let result = pack_package(args, Some(package.clone()), ci);
+            (package.clone(), result)
+        })
+        .collect();
+
+    report_results(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_only_dependencies_keep_normal_ones() {
+        let package = json!({
+            "dependencies": [
+                { "name": "bar", "kind": null, "rename": null },
+                { "name": "bar", "kind": "dev", "rename": null },
+                { "name": "baz", "kind": "dev", "rename": "qux" },
+                { "name": "quux", "kind": "build", "rename": null },
+            ]
+        });
+
+        assert_eq!(dev_only_dependencies(&package), HashSet::from(["baz"]));
+    }
+}
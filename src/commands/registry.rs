@@ -0,0 +1,263 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
+use log::{info, warn};
+use regex::Regex;
+use semver::Version;
+use serde_json::{Value, json};
+
+use crate::{
+    archive::{CrateArchive, sanitize_entry_name},
+    commands::gc::read_name_and_version,
+    utils::{
+        DEFAULT_NAME_PATTERN, determine_path, get_gooseboy_crates_folder, installed_crates,
+        sha256_file, validate_crate_name,
+    },
+};
+
+pub const INDEX_FILE: &str = "index.json";
+
+#[derive(Args)]
+pub struct RegistryArgs {
+    /// Directory of a local registry, holding an `index.json` and the published crates
+    #[arg(long, env = "GOOSEBOY_REGISTRY_INDEX")]
+    pub registry_index: PathBuf,
+}
+
+pub(crate) fn read_index(registry: &Path) -> Result<Value> {
+    let index = registry.join(INDEX_FILE);
+    if !index.exists() {
+        return Ok(json!({ "crates": {} }));
+    }
+
+    serde_json::from_slice(&fs::read(&index)?)
+        .map_err(|e| anyhow!("failed to parse registry index {index:?}: {e}"))
+}
+
+/// Reads the index of a local registry directory or fetches it from an HTTP registry.
+pub(crate) fn fetch_index(source: &str) -> Result<Value> {
+    if !source.starts_with("http://") && !source.starts_with("https://") {
+        return read_index(Path::new(source));
+    }
+
+    let url = format!("{}/{INDEX_FILE}", source.trim_end_matches('/'));
+    let body = ureq::get(&url)
+        .call()
+        .map_err(|e| anyhow!("failed to fetch {url}: {e}"))?
+        .body_mut()
+        .read_to_string()?;
+
+    serde_json::from_str(&body).map_err(|e| anyhow!("failed to parse registry index {url}: {e}"))
+}
+
+fn write_index(registry: &Path, index: &Value) -> Result<()> {
+    fs::write(registry.join(INDEX_FILE), serde_json::to_vec_pretty(index)?)?;
+    Ok(())
+}
+
+fn entry_version(entry: &Value) -> Option<Version> {
+    entry["version"]
+        .as_str()
+        .and_then(|v| Version::parse(v).ok())
+}
+
+/// Returns the published entry matching `version`, or the newest one.
+pub(crate) fn find_version<'a>(versions: &'a [Value], version: Option<&str>) -> Option<&'a Value> {
+    match version {
+        Some(version) => versions
+            .iter()
+            .find(|entry| entry["version"].as_str() == Some(version)),
+        None => versions.iter().max_by_key(|entry| entry_version(entry)),
+    }
+}
+
+/// Resolves the published file of `entry` and checks it against the recorded checksum.
+fn verified_crate_file(registry: &Path, name: &str, entry: &Value) -> Result<PathBuf> {
+    let file = entry["file"]
+        .as_str()
+        .ok_or_else(|| anyhow!("registry index: {name} has no `file`"))?;
+    // the index may come from anywhere, its paths must stay inside the registry
+    let src = registry
+        .join(sanitize_entry_name(file).map_err(|e| anyhow!("registry index: {name}: {e}"))?);
+    if entry["checksum"].as_str() != Some(sha256_file(&src)?.as_str()) {
+        bail!("checksum mismatch for {src:?}, the registry may be corrupted");
+    }
+
+    Ok(src)
+}
+
+pub fn run_search_command(
+    query: &str,
+    registry: Option<&str>,
+    limit: Option<usize>,
+    json: bool,
+) -> Result<()> {
+    let registry = registry.ok_or_else(|| {
+        anyhow!("no registry given, pass --registry-index or set GOOSEBOY_REGISTRY_INDEX")
+    })?;
+    let index = fetch_index(registry)?;
+    let query = query.to_lowercase();
+
+    let mut results = index["crates"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, versions)| {
+            let latest = find_version(versions.as_array()?, None)?;
+            let description = latest["description"].as_str().unwrap_or_default();
+            (name.to_lowercase().contains(&query) || description.to_lowercase().contains(&query))
+                .then(|| {
+                    json!({
+                        "name": name,
+                        "version": latest["version"],
+                        "description": description,
+                    })
+                })
+        })
+        .collect::<Vec<_>>();
+    results.truncate(limit.unwrap_or(usize::MAX));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+
+    for result in results {
+        println!(
+            "{} {} - {}",
+            result["name"].as_str().unwrap_or_default(),
+            result["version"].as_str().unwrap_or_default(),
+            result["description"].as_str().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+pub fn run_publish_command(crate_path: &str, registry: &RegistryArgs) -> Result<()> {
+    let crate_path = Path::new(crate_path);
+    let (name, version) = read_name_and_version(crate_path)?;
+    let crate_json = CrateArchive::open(crate_path)?.crate_json()?;
+    let version = version.to_string();
+
+    // both end up in the published file name
+    let name = validate_crate_name(&name, &Regex::new(DEFAULT_NAME_PATTERN)?)?;
+    if version.contains(['/', '\\']) || version.contains("..") {
+        bail!("crate version {version:?} can't be used in a file name");
+    }
+
+    let mut index = read_index(&registry.registry_index)?;
+    let versions = index["crates"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("registry index: `crates` must be an object"))?
+        .entry(name.clone())
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("registry index: `{name}` must be an array"))?;
+
+    if find_version(versions, Some(&version)).is_some() {
+        bail!("{name} {version} is already published");
+    }
+
+    let file = format!("{name}-{version}.gbcrate");
+    let crates_dir = registry.registry_index.join("crates");
+    fs::create_dir_all(&crates_dir)?;
+    fs::copy(crate_path, crates_dir.join(&file))?;
+
+    versions.push(json!({
+        "version": version,
+        "description": crate_json.get("description").cloned().unwrap_or(Value::Null),
+        "file": format!("crates/{file}"),
+        "checksum": sha256_file(crate_path)?,
+    }));
+    write_index(&registry.registry_index, &index)?;
+
+    info!(
+        "published {name} {version} to {:?}",
+        registry.registry_index
+    );
+    Ok(())
+}
+
+pub fn run_install_command(
+    name: &str,
+    version: Option<&str>,
+    registry: &RegistryArgs,
+    destination_path: Option<String>,
+) -> Result<()> {
+    let index = read_index(&registry.registry_index)?;
+    let versions = index["crates"][name]
+        .as_array()
+        .ok_or_else(|| anyhow!("{name} is not in the registry"))?;
+    let entry = find_version(versions, version).ok_or_else(|| {
+        anyhow!(
+            "{name} {} is not in the registry",
+            version.unwrap_or("(any version)")
+        )
+    })?;
+
+    let src = verified_crate_file(&registry.registry_index, name, entry)?;
+
+    let folder = determine_path(destination_path, get_gooseboy_crates_folder()?);
+    fs::create_dir_all(&folder)?;
+    fs::copy(&src, folder.join(format!("{name}.gbcrate")))?;
+
+    info!(
+        "installed {name} {} to {folder:?}",
+        entry["version"].as_str().unwrap_or_default()
+    );
+    Ok(())
+}
+
+pub fn run_update_command(
+    registry: &RegistryArgs,
+    dry_run: bool,
+    destination_path: Option<String>,
+) -> Result<()> {
+    let folder = determine_path(destination_path, get_gooseboy_crates_folder()?);
+    let index = read_index(&registry.registry_index)?;
+
+    let mut updated = 0;
+    for (path, _) in installed_crates(&folder)? {
+        let (name, current) = match read_name_and_version(&path) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("skipping {path:?}: {e}");
+                continue;
+            }
+        };
+
+        let Some(latest) = index["crates"][&name]
+            .as_array()
+            .and_then(|versions| find_version(versions, None))
+        else {
+            continue;
+        };
+        let Some(version) = entry_version(latest) else {
+            continue;
+        };
+        if version <= current {
+            continue;
+        }
+
+        updated += 1;
+        if dry_run {
+            info!("would update {name} {current} -> {version}");
+            continue;
+        }
+
+        let src = verified_crate_file(&registry.registry_index, &name, latest)?;
+        fs::copy(&src, &path)?;
+        info!("updated {name} {current} -> {version}");
+    }
+
+    if updated == 0 {
+        info!("all installed crates are up to date");
+    }
+
+    Ok(())
+}
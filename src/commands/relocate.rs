@@ -0,0 +1,85 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result, bail};
+use log::{info, trace, warn};
+
+use crate::utils::{get_gooseboy_crates_folder, get_gooseboy_home, is_target_folder};
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        // rename fails across filesystems, fall back to copying
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
+
+/// Moves the crates of `folder` to `new_folder`, recursing into the `<target>/` subfolders of
+/// other targets, and returns how many were moved.
+fn move_crates(folder: &Path, new_folder: &Path) -> Result<usize> {
+    fs::create_dir_all(new_folder)?;
+
+    let mut moved = 0;
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let crate_path = entry.path();
+        let filename = entry.file_name();
+
+        if entry.file_type()?.is_dir() {
+            if is_target_folder(&filename.to_string_lossy()) {
+                moved += move_crates(&crate_path, &new_folder.join(&filename))?;
+                // only remove it once empty, it may hold files that aren't crates
+                if fs::remove_dir(&crate_path).is_err() {
+                    warn!("{crate_path:?} is not empty after moving its crates, leaving it");
+                }
+            }
+            continue;
+        }
+        if crate_path.extension().is_none_or(|e| e != "gbcrate") {
+            continue;
+        }
+
+        let dst = new_folder.join(&filename);
+        if dst.exists() {
+            bail!("{dst:?} already exists, refusing to overwrite it");
+        }
+
+        trace!("moving {crate_path:?} to {dst:?}");
+        move_file(&crate_path, &dst)?;
+        moved += 1;
+    }
+
+    Ok(moved)
+}
+
+pub fn run_relocate_command(new_path: &str) -> Result<()> {
+    let folder = get_gooseboy_crates_folder()?;
+    let new_folder = Path::new(new_path);
+    fs::create_dir_all(new_folder)?;
+
+    if fs::canonicalize(&folder)? == fs::canonicalize(new_folder)? {
+        bail!("{new_folder:?} is already the crates folder");
+    }
+
+    let moved = move_crates(&folder, new_folder)?;
+    info!("moved {moved} crate(s) from {folder:?} to {new_folder:?}");
+
+    // the crates folder is always `$GOOSEBOY_HOME/crates`
+    let new_folder = fs::canonicalize(new_folder)?;
+    match new_folder.parent() {
+        Some(home) if new_folder.ends_with("crates") => {
+            if fs::canonicalize(get_gooseboy_home()?).ok().as_deref() != Some(home) {
+                info!(
+                    "set GOOSEBOY_HOME={} for cargo-gooseboy to use the new folder",
+                    home.display()
+                );
+            }
+        }
+        _ => warn!(
+            "cargo-gooseboy looks for crates in `$GOOSEBOY_HOME/crates`, name the new folder `crates` to use it through GOOSEBOY_HOME"
+        ),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,8 @@
+use anyhow::Result;
+
+use crate::schema::crate_json_schema;
+
+pub fn run_schema_command() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&crate_json_schema())?);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::fs;
+
+use anyhow::{Result, bail};
+
+use crate::{
+    utils::{
+        CrateType, TARGET, get_cargo_metadata, get_wasm_path, resolve_path_and_package,
+        resolve_project_dir,
+    },
+    wasm::function_sizes,
+};
+
+/// Prints the `top` largest functions of the built wasm with their share of the code section,
+/// read-only and without building.
+pub fn run_size_tree_command(
+    release: bool,
+    crate_type: CrateType,
+    top: usize,
+    package: Option<String>,
+) -> Result<()> {
+    let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
+    let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
+    let metadata = get_cargo_metadata(&path, false)?;
+    let (_filename, wasm_path) = get_wasm_path(&path, TARGET, release, crate_type, &metadata)?;
+
+    if !wasm_path.exists() {
+        bail!("{wasm_path:?} does not exist yet, run `cargo gooseboy build` first");
+    }
+
+    let wasm = fs::read(&wasm_path)?;
+    let sizes = function_sizes(&wasm)?;
+    let code_size = sizes.iter().map(|(_, size)| size).sum::<u64>();
+    let percent = |size: u64| size as f64 * 100.0 / code_size.max(1) as f64;
+
+    println!(
+        "{}: {} bytes, {code_size} bytes of code in {} function(s)",
+        wasm_path.display(),
+        wasm.len(),
+        sizes.len()
+    );
+    println!("{:>10} {:>7}  function", "bytes", "code %");
+    for (name, size) in sizes.iter().take(top) {
+        println!("{size:>10} {:>6.2}%  {name}", percent(*size));
+    }
+
+    let rest = &sizes[top.min(sizes.len())..];
+    if !rest.is_empty() {
+        let rest_size = rest.iter().map(|(_, size)| size).sum::<u64>();
+        println!(
+            "{rest_size:>10} {:>6.2}%  ... {} more function(s)",
+            percent(rest_size),
+            rest.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+use clap::{Args, Command, FromArgMatches};
+use log::info;
+use serde_json::Value;
+use toml_edit::Item;
+
+use crate::{
+    commands::{
+        build::BuildArgs,
+        pack::{PackArgs, pack_package},
+    },
+    config::{CONFIG_FILE, read_config},
+    utils::{
+        get_cargo_metadata, get_package, resolve_path_and_package, resolve_project_dir, run_command,
+    },
+};
+
+pub const DEFAULT_TEST_MANIFEST: &str = "tests.json";
+
+/// The test runner of the package, `test-runner` in `[package.metadata.gooseboy]` or else
+/// in the config file, along with its test manifest.
+fn test_runner(path: &PathBuf) -> Result<(String, PathBuf)> {
+    let metadata = get_cargo_metadata(path, false)?;
+    let gooseboy = &get_package(path, &metadata)?["metadata"]["gooseboy"];
+
+    let runner = match gooseboy.get("test-runner") {
+        Some(runner) => runner
+            .as_str()
+            .ok_or_else(|| anyhow!("`package.metadata.gooseboy.test-runner` must be a string"))?
+            .to_string(),
+        None => read_config()?
+            .get("test-runner")
+            .and_then(Item::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no test runner configured, set `test-runner` to the host binary that runs the tests in [package.metadata.gooseboy] of Cargo.toml or in {CONFIG_FILE} in the gooseboy home"
+                )
+            })?,
+    };
+
+    let manifest = path.join(
+        gooseboy
+            .get("test-manifest")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_TEST_MANIFEST),
+    );
+    if !manifest.is_file() {
+        bail!(
+            "test manifest {manifest:?} does not exist, create it or point `package.metadata.gooseboy.test-manifest` at it"
+        );
+    }
+
+    Ok((runner, manifest))
+}
+
+/// Packs the crate without installing it and runs the test runner as
+/// `<runner> <crate> <manifest> [runner args...]`.
+pub fn run_test_command(
+    build: BuildArgs,
+    package: Option<String>,
+    runner_args: &[String],
+    ci: bool,
+) -> Result<()> {
+    if build.workspace {
+        bail!("`test` runs a single package, pass its name instead of --workspace");
+    }
+
+    let (path_arg, package_name_opt) = resolve_path_and_package(package.clone())?;
+    let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
+    let (runner, manifest) = test_runner(&path)?;
+
+    // the default pack settings, without copying the crate into the gooseboy home
+    let matches = PackArgs::augment_args(Command::new("pack")).try_get_matches_from([
+        "pack",
+        "--no-copy",
+        "--quiet",
+    ])?;
+    let mut args = PackArgs::from_arg_matches(&matches)?;
+    args.build = build;
+    args.package = package;
+
+    let packed = pack_package(&args, args.package.clone(), ci)?;
+
+    let packed = packed.display().to_string();
+    let manifest = manifest.display().to_string();
+    let mut command_args = vec![packed.as_str(), manifest.as_str()];
+    command_args.extend(runner_args.iter().map(String::as_str));
+
+    info!("running tests with {runner}");
+    run_command(&path, &runner, &command_args).map_err(|e| anyhow!("tests failed: {e}"))
+}
@@ -0,0 +1,149 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Result, anyhow, bail};
+use clap::builder::styling::Style;
+use clap_cargo::style;
+use log::{debug, info, warn};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    archive::{CHECKSUMS_ENTRY, CrateArchive},
+    schema::{Diagnostic, Severity, diagnose_crate_json, lint_crate_json},
+    utils::validate_crate_name,
+    wasm::{read_exports, validate_wasm},
+};
+
+/// Runs every check on the crate and collects the problems instead of stopping at the
+/// first, also returning the sorted exports of its wasm module when it could be read.
+pub(crate) fn diagnose_crate(
+    crate_path: &Path,
+    name_pattern: &Regex,
+) -> Result<(Vec<Diagnostic>, Vec<String>)> {
+    let archive = CrateArchive::open(crate_path)?;
+    let mut diagnostics = Vec::new();
+
+    match archive.verify_checksums() {
+        Result::Ok(Some(algo)) => debug!("checksums verified with {}", algo.name()),
+        Result::Ok(None) => {}
+        Err(e) => diagnostics.push(Diagnostic::error("", e.to_string()).in_file(CHECKSUMS_ENTRY)),
+    }
+
+    let crate_json = match archive.crate_json() {
+        Result::Ok(crate_json) => crate_json,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error("", e.to_string()));
+            return Ok((diagnostics, Vec::new()));
+        }
+    };
+    diagnostics.extend(diagnose_crate_json(&crate_json));
+    diagnostics.extend(lint_crate_json(&crate_json));
+    if let Some(name) = crate_json.get("name").and_then(Value::as_str)
+        && let Err(e) = validate_crate_name(name, name_pattern)
+    {
+        diagnostics.push(Diagnostic::error("", e.to_string()));
+    }
+
+    // a non-string entrypoint is already reported by the schema check
+    let entrypoint = crate_json
+        .get("entrypoint")
+        .and_then(Value::as_str)
+        .unwrap_or("app.wasm");
+    let exports = archive.read_entry(entrypoint).and_then(|wasm| {
+        validate_wasm(wasm)?;
+        read_exports(wasm)
+    });
+    match exports {
+        Result::Ok(exports) => Ok((diagnostics, exports)),
+        Err(e) => {
+            diagnostics.push(Diagnostic::error("", e.to_string()).in_file(entrypoint));
+            Ok((diagnostics, Vec::new()))
+        }
+    }
+}
+
+/// Validates the crate and returns the sorted exports of its wasm module.
+pub fn verify_crate(crate_path: &Path, name_pattern: &Regex) -> Result<Vec<String>> {
+    let (diagnostics, exports) = diagnose_crate(crate_path, name_pattern)?;
+
+    let mut errors = Vec::new();
+    for diagnostic in &diagnostics {
+        match diagnostic.severity {
+            Severity::Error => errors.push(diagnostic),
+            Severity::Warning => warn!("{diagnostic}"),
+        }
+    }
+    match errors.as_slice() {
+        [] => Ok(exports),
+        [error] => bail!("{error}"),
+        [error, rest @ ..] => bail!("{error} (and {} more error(s))", rest.len()),
+    }
+}
+
+/// Prints the diagnostics grouped by file, errors before warnings.
+fn print_report(diagnostics: &[Diagnostic], color: bool) {
+    let paint = |style: Style| if color { style } else { Style::new() };
+
+    let mut files = BTreeMap::<&str, Vec<&Diagnostic>>::new();
+    for diagnostic in diagnostics {
+        files.entry(&diagnostic.file).or_default().push(diagnostic);
+    }
+
+    for (file, mut diagnostics) in files {
+        diagnostics.sort_by_key(|d| d.severity);
+
+        let header = paint(style::HEADER);
+        println!("{header}{file}{header:#}");
+        for diagnostic in diagnostics {
+            let (label, style) = match diagnostic.severity {
+                Severity::Error => ("error", paint(style::ERROR)),
+                Severity::Warning => ("warning", paint(style::WARN)),
+            };
+            if diagnostic.field.is_empty() {
+                println!("  {style}{label}{style:#}: {}", diagnostic.problem);
+            } else {
+                println!(
+                    "  {style}{label}{style:#}: `{}` {}",
+                    diagnostic.field, diagnostic.problem
+                );
+            }
+        }
+    }
+}
+
+pub fn run_verify_command(crate_path: &str, name_pattern: &Regex, color: bool) -> Result<()> {
+    let (diagnostics, exports) = diagnose_crate(Path::new(crate_path), name_pattern)?;
+
+    if !diagnostics.is_empty() {
+        print_report(&diagnostics, color);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("{crate_path} is invalid, {errors} error(s) found");
+    }
+    info!("{crate_path} is valid");
+
+    println!("exports:");
+    for export in exports {
+        println!("  {export}");
+    }
+
+    Ok(())
+}
+
+pub fn run_validate_wasm_command(path: &str) -> Result<()> {
+    let wasm = fs::read(path).map_err(|e| anyhow!("failed to read {path}: {e}"))?;
+    let functions = validate_wasm(&wasm)?;
+    let exports = read_exports(&wasm)?;
+
+    println!(
+        "valid wasm, {functions} functions, {} exports",
+        exports.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use anyhow::{Result, bail};
+
+use crate::utils::{
+    CrateType, TARGET, get_cargo_metadata, get_default_members, get_wasm_path,
+    resolve_path_and_package, resolve_project_dir,
+};
+
+pub fn run_wasm_path_command(
+    release: bool,
+    crate_type: CrateType,
+    package: Option<String>,
+) -> Result<()> {
+    let (path_arg, package_name_opt) = resolve_path_and_package(package)?;
+    let path = resolve_project_dir(&path_arg, package_name_opt.as_deref())?;
+    let members = match package_name_opt {
+        Some(_) => None,
+        None => get_default_members(&path)?,
+    };
+
+    for path in members.unwrap_or_else(|| vec![path]) {
+        let metadata = get_cargo_metadata(&path, false)?;
+        let (_filename, wasm_path) = get_wasm_path(&path, TARGET, release, crate_type, &metadata)?;
+
+        if !wasm_path.exists() {
+            bail!("{wasm_path:?} does not exist yet, run `cargo gooseboy build` first");
+        }
+
+        println!("{}", std::fs::canonicalize(wasm_path)?.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use std::{collections::HashMap, env, fs};
+
+use anyhow::{Result, anyhow, bail};
+use clap::{Arg, ArgMatches, Command, ValueEnum, parser::ValueSource};
+use serde_json::{Map, json};
+use toml_edit::{DocumentMut, Item, Value};
+
+use crate::{
+    archive::EntryCompression,
+    utils::{TARGET, get_gooseboy_home},
+};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Reads `config.toml` from the gooseboy home, an empty document when there is none.
+pub fn read_config() -> Result<DocumentMut> {
+    let path = get_gooseboy_home()?.join(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    fs::read_to_string(&path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| anyhow!("failed to parse {path:?}: {e}"))
+}
+
+fn value_args(flag: &str, value: &Value, args: &mut Vec<String>) -> Result<()> {
+    match value {
+        Value::Boolean(b) => {
+            if *b.value() {
+                args.push(flag.to_string());
+            }
+        }
+        Value::String(s) => args.extend([flag.to_string(), s.value().clone()]),
+        Value::Integer(i) => args.extend([flag.to_string(), i.value().to_string()]),
+        Value::Float(f) => args.extend([flag.to_string(), f.value().to_string()]),
+        Value::Array(values) => {
+            for value in values {
+                value_args(flag, value, args)?;
+            }
+        }
+        _ => bail!("preset option `{flag}` must be a boolean, string, number or array"),
+    }
+
+    Ok(())
+}
+
+/// Reads the `[compression]` table mapping file extensions to the method their zip entries
+/// are compressed with, e.g. `svg = "stored"`.
+pub fn compression_overrides(config: &DocumentMut) -> Result<HashMap<String, EntryCompression>> {
+    let Some(table) = config.get("compression") else {
+        return Ok(HashMap::new());
+    };
+    let table = table
+        .as_table_like()
+        .ok_or_else(|| anyhow!("`compression` in {CONFIG_FILE} must be a table"))?;
+
+    table
+        .iter()
+        .map(|(extension, item)| {
+            let method = item
+                .as_str()
+                .and_then(|method| EntryCompression::from_str(method, true).ok())
+                .ok_or_else(|| {
+                    anyhow!("compression of `{extension}` must be \"stored\" or \"deflated\"")
+                })?;
+            Ok((extension.trim_start_matches('.').to_lowercase(), method))
+        })
+        .collect()
+}
+
+/// Turns `[presets.<name>]` into command line flags, `release = true` becomes `--release`
+/// and `max-memory = 1048576` becomes `--max-memory 1048576`.
+fn preset_args(config: &DocumentMut, name: &str) -> Result<Vec<String>> {
+    let preset = config
+        .get("presets")
+        .and_then(|presets| presets.get(name))
+        .and_then(Item::as_table_like)
+        .ok_or_else(|| anyhow!("preset {name:?} is not defined in {CONFIG_FILE}"))?;
+
+    let mut args = Vec::new();
+    for (key, item) in preset.iter() {
+        let value = item
+            .as_value()
+            .ok_or_else(|| anyhow!("preset {name:?}: `{key}` must be a value"))?;
+        value_args(&format!("--{}", key.replace('_', "-")), value, &mut args)?;
+    }
+
+    Ok(args)
+}
+
+/// A preset expanded by [`expand_preset`], along with the arguments given on the command
+/// line so [`print_config`] can tell which values the preset provided.
+pub struct AppliedPreset {
+    pub name: String,
+    pub flags: Vec<String>,
+    pub cli_args: Vec<String>,
+}
+
+impl AppliedPreset {
+    /// Whether the preset set `arg` and the command line didn't override it.
+    fn provides(&self, arg: &Arg) -> bool {
+        let Some(long) = arg.get_long() else {
+            return false;
+        };
+        let on_cli = self.cli_args.iter().any(|a| {
+            a.strip_prefix("--")
+                .is_some_and(|a| a == long || a.starts_with(&format!("{long}=")))
+                || arg.get_short().is_some_and(|short| {
+                    a.strip_prefix('-')
+                        .is_some_and(|a| !a.starts_with('-') && a.contains(short))
+                })
+        });
+
+        !on_cli && self.flags.iter().any(|flag| flag == long)
+    }
+}
+
+/// Expands `--preset <name>` into the preset's flags, inserted right after the subcommand
+/// so that flags given on the command line override them. Like the other global flags it
+/// can come before or after the subcommand.
+pub fn expand_preset(
+    mut args: Vec<String>,
+    subcommands: &[String],
+) -> Result<(Vec<String>, Option<AppliedPreset>)> {
+    let Some((index, name, after)) = args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == "--preset" {
+            args.get(i + 1).map(|name| (i, name.clone(), i + 2))
+        } else {
+            arg.strip_prefix("--preset=")
+                .map(|name| (i, name.to_string(), i + 1))
+        }
+    }) else {
+        return Ok((args, None));
+    };
+
+    let is_subcommand = |arg: &String| subcommands.contains(arg);
+    let subcommand = match args[..index].iter().rposition(is_subcommand) {
+        Some(subcommand) => subcommand,
+        None => args[after..]
+            .iter()
+            .position(is_subcommand)
+            .map(|subcommand| after + subcommand)
+            .ok_or_else(|| anyhow!("--preset needs a subcommand to apply to"))?,
+    };
+
+    let preset = preset_args(&read_config()?, &name)?;
+    let applied = AppliedPreset {
+        flags: preset
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("--").map(str::to_string))
+            .collect(),
+        cli_args: args.clone(),
+        name,
+    };
+    args.splice(subcommand + 1..subcommand + 1, preset);
+
+    Ok((args, Some(applied)))
+}
+
+fn value_source(matches: &ArgMatches, arg: &Arg, preset: Option<&AppliedPreset>) -> String {
+    match matches.value_source(arg.get_id().as_str()) {
+        Some(ValueSource::CommandLine) => match preset {
+            Some(preset) if preset.provides(arg) => format!("preset {}", preset.name),
+            _ => "command line".to_string(),
+        },
+        Some(ValueSource::EnvVariable) => "environment".to_string(),
+        Some(ValueSource::DefaultValue) => "default".to_string(),
+        _ => "unset".to_string(),
+    }
+}
+
+/// Prints the value of every argument of `command` along with where it came from, plus
+/// the settings that don't come from arguments.
+pub fn print_config(
+    command: &Command,
+    matches: &ArgMatches,
+    preset: Option<&AppliedPreset>,
+    format: ConfigFormat,
+) -> Result<()> {
+    let mut config = Vec::new();
+
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if matches!(id, "help" | "version" | "print_config") {
+            continue;
+        }
+
+        let value = matches
+            .get_raw(id)
+            .map(|values| {
+                values
+                    .map(|v| v.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+        config.push((
+            arg.get_long().unwrap_or(id).to_string(),
+            value,
+            value_source(matches, arg, preset),
+        ));
+    }
+
+    let home_source = if env::var_os("GOOSEBOY_HOME").is_some() {
+        "environment"
+    } else {
+        "default"
+    };
+    let home = get_gooseboy_home()?;
+    config.extend([
+        (
+            "target".to_string(),
+            TARGET.to_string(),
+            "built-in".to_string(),
+        ),
+        (
+            "gooseboy-home".to_string(),
+            home.display().to_string(),
+            home_source.to_string(),
+        ),
+        (
+            "config-file".to_string(),
+            home.join(CONFIG_FILE).display().to_string(),
+            home_source.to_string(),
+        ),
+    ]);
+
+    match format {
+        ConfigFormat::Json => {
+            let config = config
+                .into_iter()
+                .map(|(name, value, source)| (name, json!({ "value": value, "source": source })))
+                .collect::<Map<_, _>>();
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        ConfigFormat::Table => {
+            let width = config
+                .iter()
+                .map(|(name, _, _)| name.len())
+                .max()
+                .unwrap_or(0);
+            for (name, value, source) in config {
+                println!("{name:width$}  {value}  ({source})");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::{Result, anyhow};
+use clap::ColorChoice;
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+
+/// Forwards records to the console logger and additionally writes every record,
+/// down to trace level, to a file as plain text.
+struct TraceFileLogger {
+    console: env_logger::Logger,
+    file: Mutex<File>,
+    // messages can carry colored tool output, which would make the file hard to grep
+    ansi_escapes: Regex,
+}
+
+impl Log for TraceFileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if self.console.matches(record) {
+            self.console.log(record);
+        }
+
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(
+                file,
+                "[{} {:<5} {}] {}",
+                jiff::Timestamp::now().strftime("%Y-%m-%dT%H:%M:%SZ"),
+                record.level(),
+                record.target(),
+                self.ansi_escapes
+                    .replace_all(&record.args().to_string(), "")
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Whether reports printed to stdout should be colored, never with `--ci` and otherwise
+/// `auto` colors a terminal unless `NO_COLOR` is set.
+pub fn use_color(ci: bool, color: ColorChoice) -> bool {
+    match color {
+        _ if ci => false,
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+pub fn init_logger(
+    ci: bool,
+    color: ColorChoice,
+    trace_file: Option<&Path>,
+    append: bool,
+) -> Result<()> {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    builder.write_style(match color {
+        ColorChoice::Always => env_logger::WriteStyle::Always,
+        ColorChoice::Never => env_logger::WriteStyle::Never,
+        ColorChoice::Auto => env_logger::WriteStyle::Auto,
+    });
+
+    if ci {
+        builder
+            .write_style(env_logger::WriteStyle::Never)
+            .format(|buf, record| {
+                writeln!(
+                    buf,
+                    "{}",
+                    serde_json::json!({
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "message": record.args().to_string(),
+                    })
+                )
+            });
+    }
+
+    let Some(trace_file) = trace_file else {
+        builder.init();
+        return Ok(());
+    };
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(trace_file)
+        .map_err(|e| anyhow!("failed to open trace file {trace_file:?}: {e}"))?;
+
+    log::set_boxed_logger(Box::new(TraceFileLogger {
+        console: builder.build(),
+        file: Mutex::new(file),
+        ansi_escapes: Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]")?,
+    }))?;
+    log::set_max_level(LevelFilter::Trace);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::Level;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn trace_file_has_no_escape_sequences() {
+        let trace_file = NamedTempFile::new().unwrap();
+        let logger = TraceFileLogger {
+            console: env_logger::Builder::new()
+                .filter_level(LevelFilter::Off)
+                .build(),
+            file: Mutex::new(trace_file.reopen().unwrap()),
+            ansi_escapes: Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]").unwrap(),
+        };
+
+        logger.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("cargo")
+                .args(format_args!(
+                    "\x1b[1m\x1b[33mwarning\x1b[0m: unused variable \x1b[?25l`x`\x1b[K"
+                ))
+                .build(),
+        );
+        logger.flush();
+
+        let contents = fs::read_to_string(trace_file.path()).unwrap();
+        assert!(!contents.contains('\x1b'), "{contents:?}");
+        assert!(
+            contents.contains("warning: unused variable `x`"),
+            "{contents:?}"
+        );
+    }
+
+    #[test]
+    fn ci_never_colors() {
+        for color in [ColorChoice::Always, ColorChoice::Auto, ColorChoice::Never] {
+            assert!(!use_color(true, color));
+        }
+    }
+}
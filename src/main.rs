@@ -1,12 +1,47 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::unnecessary_debug_formatting)]
+use std::path::PathBuf;
+
 use anyhow::{Ok, Result};
-use clap::{Parser, Subcommand};
+use clap::{ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand};
+use regex::Regex;
 
-use crate::commands::{build::run_build_command, new::run_new_command, pack::run_pack_command};
+use crate::{
+    commands::{
+        audit::run_audit_command,
+        build::{BuildArgs, run_build_command},
+        check::run_check_command,
+        diff::run_diff_command,
+        export::run_export_command,
+        gc::run_gc_command,
+        info::run_info_command,
+        lock::{DEFAULT_LOCKFILE, run_freeze_command, run_sync_command},
+        metadata::{METADATA_FORMAT_VERSION, run_metadata_command},
+        new::run_new_command,
+        pack::{PackArgs, run_pack_command},
+        registry::{
+            RegistryArgs, run_install_command, run_publish_command, run_search_command,
+            run_update_command,
+        },
+        relocate::run_relocate_command,
+        schema::run_schema_command,
+        size_tree::run_size_tree_command,
+        test::run_test_command,
+        verify::{run_validate_wasm_command, run_verify_command},
+        wasm_path::run_wasm_path_command,
+    },
+    config::{expand_preset, print_config},
+    logger::{init_logger, use_color},
+    utils::{CrateType, DEFAULT_NAME_PATTERN},
+};
 
+pub mod archive;
 pub mod commands;
+pub mod config;
+pub mod logger;
+pub mod schema;
 pub mod utils;
+pub mod wasm;
 
 pub const CLAP_STYLING: clap::builder::styling::Styles = clap::builder::styling::Styles::styled()
     .header(clap_cargo::style::HEADER)
@@ -22,6 +57,7 @@ pub const CLAP_STYLING: clap::builder::styling::Styles = clap::builder::styling:
 #[command(bin_name = "cargo")]
 #[command(styles = CLAP_STYLING)]
 #[command(version = "1.0")]
+#[command(args_override_self = true)]
 #[command(about = "gooseboy command line tool", long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
@@ -31,6 +67,22 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum SCommands {
     Gooseboy {
+        /// JSON logs without colors, implies `--require-target` and `pack --verify-after-pack`,
+        /// also enabled when `CI=true`
+        #[arg(long, global = true)]
+        ci: bool,
+        /// Also write trace-level logs to this file
+        #[arg(long, global = true)]
+        trace_file: Option<PathBuf>,
+        /// Append to the trace file instead of truncating it
+        #[arg(long, global = true, requires = "trace_file")]
+        trace_append: bool,
+        /// When to color console output, `--ci` never colors it
+        #[arg(long, global = true, value_enum, default_value_t)]
+        color: ColorChoice,
+        /// Apply the flags of a `[presets.<name>]` table from the config file
+        #[arg(long, global = true)]
+        preset: Option<String>,
         #[command(subcommand)]
         command: Commands,
     },
@@ -49,24 +101,191 @@ pub enum Commands {
         library: bool,
     },
     Build {
+        #[command(flatten)]
+        args: BuildArgs,
+        package: Option<String>,
+    },
+    Check {
+        #[command(flatten)]
+        args: BuildArgs,
+        package: Option<String>,
+    },
+    Pack(Box<PackArgs>),
+    /// Pack the crate and run the configured test runner on it, arguments after `--` are
+    /// passed to the runner
+    Test {
+        #[command(flatten)]
+        args: BuildArgs,
+        package: Option<String>,
+        #[arg(last = true)]
+        runner_args: Vec<String>,
+    },
+    Info {
+        crate_path: String,
+    },
+    Diff {
+        a: String,
+        b: String,
+    },
+    Export {
+        crate_path: String,
+        /// Extract only this entry, to stdout unless an output path is given
+        #[arg(long, alias = "extract-only", value_name = "ENTRY")]
+        extract: Option<String>,
+        out: Option<String>,
+    },
+    Freeze {
+        #[arg(long, default_value = DEFAULT_LOCKFILE)]
+        lockfile: PathBuf,
+        path: Option<String>,
+    },
+    Sync {
+        #[arg(long, default_value = DEFAULT_LOCKFILE)]
+        lockfile: PathBuf,
+        /// Folder to look for locked crates missing from the crates folder, can be repeated
+        #[arg(long)]
+        from: Vec<PathBuf>,
+        #[arg(long)]
+        dry_run: bool,
+        path: Option<String>,
+    },
+    Gc {
+        /// How many versions of each crate to keep
+        #[arg(long, default_value_t = 1)]
+        keep: usize,
+        #[arg(long)]
+        dry_run: bool,
+        path: Option<String>,
+    },
+    Publish {
+        crate_path: String,
+        #[command(flatten)]
+        registry: RegistryArgs,
+    },
+    Install {
+        name: String,
+        #[arg(long)]
+        version: Option<String>,
+        #[command(flatten)]
+        registry: RegistryArgs,
+        destination_path: Option<String>,
+    },
+    Update {
+        #[command(flatten)]
+        registry: RegistryArgs,
+        /// List the crates that would be updated without replacing them
+        #[arg(long)]
+        dry_run: bool,
+        destination_path: Option<String>,
+    },
+    Search {
+        query: String,
+        /// Local registry directory or HTTP registry URL
+        #[arg(
+            long = "registry-index",
+            alias = "registry",
+            env = "GOOSEBOY_REGISTRY_INDEX"
+        )]
+        registry: Option<String>,
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long)]
+        json: bool,
+    },
+    Relocate {
+        new_path: String,
+    },
+    Verify {
+        crate_path: String,
+        #[arg(long, default_value = DEFAULT_NAME_PATTERN)]
+        name_pattern: Regex,
+    },
+    /// Verify every installed crate, other targets included, failing if any is corrupt or
+    /// has an unsupported crate.json schema version
+    Audit {
+        #[arg(long, default_value = DEFAULT_NAME_PATTERN)]
+        name_pattern: Regex,
+        path: Option<String>,
+    },
+    Schema,
+    ValidateWasm {
+        path: String,
+    },
+    Metadata {
+        #[arg(long, default_value_t = METADATA_FORMAT_VERSION)]
+        format_version: u32,
+        /// Also list the resolved versions of the package's dependencies
+        #[arg(long)]
+        deps: bool,
+        package: Option<String>,
+    },
+    WasmPath {
         #[arg(short, long)]
         release: bool,
+        #[arg(long, value_enum, default_value_t)]
+        crate_type: CrateType,
         package: Option<String>,
     },
-    Pack {
+    /// Print the largest functions of the built wasm, to find what bloats it
+    SizeTree {
         #[arg(short, long)]
         release: bool,
-        #[arg(long)]
-        no_copy: bool,
+        #[arg(long, value_enum, default_value_t)]
+        crate_type: CrateType,
+        /// Number of functions to list, the rest are summed up
+        #[arg(long, default_value_t = 20)]
+        top: usize,
         package: Option<String>,
-        destination_path: Option<String>,
     },
 }
 
+fn is_ci_env() -> bool {
+    std::env::var("CI").is_ok_and(|v| v.eq_ignore_ascii_case("true") || v == "1")
+}
+
+fn subcommand_names() -> Vec<String> {
+    Cli::command()
+        .find_subcommand("gooseboy")
+        .map(|gooseboy| {
+            gooseboy
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 pub fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let (args, preset) = expand_preset(std::env::args().collect(), &subcommand_names())?;
+    let mut cli = Cli::command();
+    let matches = cli
+        .try_get_matches_from_mut(args)
+        .unwrap_or_else(|e| e.exit());
+    let SCommands::Gooseboy {
+        ci,
+        trace_file,
+        trace_append,
+        color,
+        preset: _,
+        mut command,
+    } = Cli::from_arg_matches(&matches)
+        .unwrap_or_else(|e| e.exit())
+        .command;
+    let ci = ci || is_ci_env();
 
-    let SCommands::Gooseboy { command } = Cli::parse().command;
+    init_logger(ci, color, trace_file.as_deref(), trace_append)?;
+    let color = use_color(ci, color);
+
+    // fail on a missing target rather than leaving it to cargo
+    match &mut command {
+        Commands::Build { args, .. }
+        | Commands::Check { args, .. }
+        | Commands::Test { args, .. } => {
+            args.require_target |= ci;
+        }
+        Commands::Pack(args) => args.build.require_target |= ci,
+        _ => {}
+    }
 
     match command {
         Commands::New {
@@ -75,13 +294,91 @@ pub fn main() -> Result<()> {
             buildscript,
             library,
         } => run_new_command(package, no_std, buildscript, !library)?,
-        Commands::Build { release, package } => run_build_command(release, package)?,
-        Commands::Pack {
-            release,
+        Commands::Build { args, package } => run_build_command(&args, package)?,
+        Commands::Check { args, package } => run_check_command(&args, package)?,
+        Commands::Pack(args) => match args.print_config {
+            Some(format) => {
+                let pack = cli
+                    .find_subcommand("gooseboy")
+                    .and_then(|gooseboy| gooseboy.find_subcommand("pack"))
+                    .expect("pack subcommand");
+                let pack_matches = matches
+                    .subcommand_matches("gooseboy")
+                    .and_then(|gooseboy| gooseboy.subcommand_matches("pack"))
+                    .expect("pack subcommand matches");
+                print_config(pack, pack_matches, preset.as_ref(), format)?;
+            }
+            None => run_pack_command(*args, ci)?,
+        },
+        Commands::Test {
+            args,
             package,
+            runner_args,
+        } => run_test_command(args, package, &runner_args, ci)?,
+        Commands::Info { crate_path } => run_info_command(&crate_path)?,
+        Commands::Diff { a, b } => run_diff_command(&a, &b)?,
+        Commands::Export {
+            crate_path,
+            extract,
+            out,
+        } => run_export_command(&crate_path, extract.as_deref(), out)?,
+        Commands::Freeze { lockfile, path } => run_freeze_command(&lockfile, path)?,
+        Commands::Sync {
+            lockfile,
+            from,
+            dry_run,
+            path,
+        } => run_sync_command(&lockfile, &from, dry_run, path)?,
+        Commands::Gc {
+            keep,
+            dry_run,
+            path,
+        } => run_gc_command(keep, dry_run, path)?,
+        Commands::Publish {
+            crate_path,
+            registry,
+        } => run_publish_command(&crate_path, &registry)?,
+        Commands::Install {
+            name,
+            version,
+            registry,
             destination_path,
-            no_copy,
-        } => run_pack_command(release, package, destination_path, no_copy)?,
+        } => run_install_command(&name, version.as_deref(), &registry, destination_path)?,
+        Commands::Update {
+            registry,
+            dry_run,
+            destination_path,
+        } => run_update_command(&registry, dry_run, destination_path)?,
+        Commands::Search {
+            query,
+            registry,
+            limit,
+            json,
+        } => run_search_command(&query, registry.as_deref(), limit, json)?,
+        Commands::Relocate { new_path } => run_relocate_command(&new_path)?,
+        Commands::Verify {
+            crate_path,
+            name_pattern,
+        } => run_verify_command(&crate_path, &name_pattern, color)?,
+        Commands::Audit { name_pattern, path } => run_audit_command(path, &name_pattern, color)?,
+        Commands::Schema => run_schema_command()?,
+        Commands::ValidateWasm { path } => run_validate_wasm_command(&path)?,
+        Commands::Metadata {
+            format_version,
+            deps,
+            package,
+        } => run_metadata_command(package, format_version, deps)?,
+        Commands::WasmPath {
+            release,
+            crate_type,
+            package,
+        } => run_wasm_path_command(release, crate_type, package)?,
+        Commands::SizeTree {
+            release,
+            crate_type,
+            top,
+            package,
+        } => run_size_tree_command(release, crate_type, top, package)?,
     }
 
     Ok(())
@@ -0,0 +1,272 @@
+use std::fmt;
+
+use anyhow::{Result, anyhow, bail};
+use log::warn;
+use semver::Version;
+use serde_json::{Value, json};
+
+/// The crate.json format version this CLI writes, manifests without one are treated as 1.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Resolves the version of a crate from its crate.json `version`, using `fallback` when
+/// that isn't a version string, e.g. the `1` written by `new`.
+pub(crate) fn crate_version(
+    crate_json: &Value,
+    fallback: impl FnOnce() -> Result<String>,
+) -> Result<Version> {
+    match &crate_json["version"] {
+        Value::String(version) => Version::parse(version)
+            .map_err(|e| anyhow!("crate.json: invalid `version` {version:?}: {e}")),
+        _ => Ok(Version::parse(&fallback()?)?),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single problem found while validating a crate, `field` is empty when the problem
+/// isn't about a particular field.
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+    pub file: String,
+    pub field: String,
+    pub problem: String,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, field: &str, problem: impl Into<String>) -> Self {
+        Self {
+            file: "crate.json".to_string(),
+            field: field.to_string(),
+            problem: problem.into(),
+            severity,
+        }
+    }
+
+    pub(crate) fn error(field: &str, problem: impl Into<String>) -> Self {
+        Self::new(Severity::Error, field, problem)
+    }
+
+    pub(crate) fn warning(field: &str, problem: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, field, problem)
+    }
+
+    /// Moves the diagnostic to another file of the crate.
+    pub(crate) fn in_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.field.is_empty() {
+            write!(f, "{}: {}", self.file, self.problem)
+        } else {
+            write!(f, "{}: `{}` {}", self.file, self.field, self.problem)
+        }
+    }
+}
+
+/// Deprecated crate.json fields and how to migrate away from them, reported by
+/// [`lint_crate_json`].
+const DEPRECATED_FIELDS: [(&str, &str); 1] = [(
+    "min_rust_version",
+    "set `rust-version` in the [package] table of Cargo.toml instead, cargo enforces it as well",
+)];
+
+/// JSON Schema of `crate.json`, [`diagnose_crate_json`] checks manifests against it.
+pub fn crate_json_schema() -> Value {
+    let strings = json!({ "type": "array", "items": { "type": "string" } });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "gooseboy crate.json",
+        "type": "object",
+        "required": ["name"],
+        "properties": {
+            "schema_version": {
+                "type": "integer",
+                "minimum": 1,
+                "description": "Version of the crate.json format, 1 when missing"
+            },
+            "name": { "type": "string", "description": "Crate name shown by the host" },
+            "description": { "type": "string" },
+            "version": {
+                "type": ["string", "integer"],
+                "description": "Crate version, defaults to the cargo package version"
+            },
+            "entrypoint": {
+                "type": "string",
+                "description": "Archive entry of the wasm module, `app.wasm` by default"
+            },
+            "permissions": strings,
+            "icon": { "type": "string", "description": "Square PNG or SVG icon" },
+            "assets": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Files and directories packed alongside the wasm"
+            },
+            "allowed_imports": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Permitted `module::name` imports, `module::*` allows a whole module"
+            },
+            "profiles": {
+                "type": "object",
+                "additionalProperties": { "type": "object" },
+                "description": "Named overrides selected with `pack --crate-profile`"
+            },
+            "entry_signature": {
+                "type": "string",
+                "description": "Expected signature of the `main` export, e.g. `(i32, i32) -> i32`"
+            },
+            "host_exports": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Exports the host calls besides `main` and `update`"
+            },
+            "capabilities": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Host capabilities the crate needs, e.g. `filesystem` or `network`"
+            },
+            "host_abi": {
+                "type": "string",
+                "description": "Semver requirement on the host ABI version, e.g. `^1.2`"
+            },
+            "min_rust_version": {
+                "type": "string",
+                "description": "Deprecated, use `rust-version` in Cargo.toml"
+            },
+            "features": strings,
+            "default_features": { "type": "boolean" },
+            "build_std": { "type": "boolean" },
+            "wasm_features": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "Post-MVP wasm proposals the module needs, written by `pack --wasm-features-report`"
+            },
+        },
+    })
+}
+
+fn type_matches(value: &Value, ty: &str) -> bool {
+    match ty {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn check(value: &Value, schema: &Value, path: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let types = match &schema["type"] {
+        Value::String(ty) => vec![ty.as_str()],
+        Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|ty| type_matches(value, ty)) {
+        diagnostics.push(if path.is_empty() {
+            Diagnostic::error("", "expected an object at the top level")
+        } else {
+            Diagnostic::error(path, format!("must be of type {}", types.join(" or ")))
+        });
+        return;
+    }
+
+    if let Some(required) = schema["required"].as_array() {
+        for field in required.iter().filter_map(Value::as_str) {
+            if value.get(field).is_none() {
+                diagnostics.push(Diagnostic::error(field, "required field is missing"));
+            }
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        for (key, field) in object {
+            let field_path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+            if let Some(field_schema) = schema["properties"].get(key) {
+                check(field, field_schema, &field_path, diagnostics);
+            } else if let Some(field_schema) = schema.get("additionalProperties") {
+                check(field, field_schema, &field_path, diagnostics);
+            }
+        }
+    }
+
+    if let (Some(items), Some(item_schema)) = (value.as_array(), schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            check(item, item_schema, &format!("{path}[{i}]"), diagnostics);
+        }
+    }
+}
+
+fn schema_version_diagnostic(crate_json: &Value) -> Option<Diagnostic> {
+    let version = crate_json.get("schema_version")?;
+
+    match version.as_u64() {
+        Some(0) | None => Some(Diagnostic::error(
+            "schema_version",
+            format!("has the unsupported value {version}"),
+        )),
+        Some(version) if version > SCHEMA_VERSION => Some(Diagnostic::warning(
+            "schema_version",
+            format!(
+                "is {version} but this version of cargo-gooseboy only knows {SCHEMA_VERSION}, newer fields will be ignored, consider updating"
+            ),
+        )),
+        Some(_) => None,
+    }
+}
+
+/// Errors on manifests in a format this CLI can't read and warns on newer ones, which may
+/// use fields it doesn't know about.
+pub(crate) fn check_schema_version(crate_json: &Value) -> Result<()> {
+    match schema_version_diagnostic(crate_json) {
+        Some(diagnostic) if diagnostic.severity == Severity::Error => bail!("{diagnostic}"),
+        Some(diagnostic) => warn!("{diagnostic}"),
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Sets `schema_version` on a manifest rewritten by the CLI, keeping an existing one.
+pub(crate) fn stamp_schema_version(crate_json: &mut Value) {
+    if let Some(object) = crate_json.as_object_mut() {
+        object
+            .entry("schema_version")
+            .or_insert_with(|| json!(SCHEMA_VERSION));
+    }
+}
+
+/// Warns about deprecated fields, kept apart from [`diagnose_crate_json`] since a manifest
+/// using them is still valid.
+pub(crate) fn lint_crate_json(crate_json: &Value) -> Vec<Diagnostic> {
+    DEPRECATED_FIELDS
+        .iter()
+        .filter(|(field, _)| crate_json.get(field).is_some())
+        .map(|(field, migration)| Diagnostic::warning(field, format!("is deprecated, {migration}")))
+        .collect()
+}
+
+/// Checks a crate.json against [`crate_json_schema`] and its `schema_version`, returning
+/// every problem found rather than stopping at the first.
+pub(crate) fn diagnose_crate_json(crate_json: &Value) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check(crate_json, &crate_json_schema(), "", &mut diagnostics);
+    diagnostics.extend(schema_version_diagnostic(crate_json));
+    diagnostics
+}
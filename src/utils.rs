@@ -1,45 +1,235 @@
 use std::{
-    env, fs,
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow};
-use log::trace;
+use clap::ValueEnum;
+use log::{info, trace, warn};
+use regex::Regex;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+use toml_edit::{DocumentMut, Item};
 
 pub const TARGET: &str = "wasm32-unknown-unknown";
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrateType {
+    #[default]
+    Cdylib,
+    /// Experimental, packs a command-style wasm built from a bin target
+    Bin,
+}
+pub const DEFAULT_NAME_PATTERN: &str = "^[a-z0-9_-]+$";
 
 pub(crate) fn determine_path(path: Option<String>, default: PathBuf) -> PathBuf {
     path.map_or(default, PathBuf::from)
 }
 
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c))
+    {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Quotes a remote path like [`shell_quote`], but keeps a leading `~` pointing at the
+/// remote home.
+fn remote_path_quote(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some("") => r#""$HOME""#.to_string(),
+        Some(rest) if rest.starts_with('/') => format!(r#""$HOME"{}"#, shell_quote(rest)),
+        _ => shell_quote(path),
+    }
+}
+
+/// Renders a command as a line that can be pasted into a shell, including its env overrides.
+pub(crate) fn format_command(cmd: &Command) -> String {
+    let envs = cmd.get_envs().filter_map(|(k, v)| {
+        v.map(|v| {
+            format!(
+                "{}={}",
+                k.to_string_lossy(),
+                shell_quote(&v.to_string_lossy())
+            )
+        })
+    });
+    let program = std::iter::once(shell_quote(&cmd.get_program().to_string_lossy()));
+    let args = cmd.get_args().map(|a| shell_quote(&a.to_string_lossy()));
+
+    envs.chain(program)
+        .chain(args)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub(crate) fn run_command(path: &PathBuf, command: &str, args: &[&str]) -> Result<()> {
+    run_command_with_env(path, command, args, &[])
+}
+
+pub(crate) fn run_command_with_env(
+    path: &PathBuf,
+    command: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+) -> Result<()> {
+    run_command_with_retries(path, command, args, envs, 0, None, None)
+}
+
+/// Reruns the command up to `retries` times when it fails, waiting a little longer
+/// before every attempt, and kills it once it runs past `timeout`. When `on_stdout` is
+/// given the command's stdout is captured and passed to it line by line.
+pub(crate) fn run_command_with_retries(
+    path: &PathBuf,
+    command: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+    retries: u32,
+    timeout: Option<Duration>,
+    mut on_stdout: Option<&mut dyn FnMut(&str)>,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match run_command_once(path, command, args, envs, timeout, on_stdout.as_deref_mut()) {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                warn!("{e}, retrying ({attempt}/{retries})");
+                thread::sleep(RETRY_DELAY * attempt);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn run_command_once(
+    path: &PathBuf,
+    command: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+    timeout: Option<Duration>,
+    mut on_stdout: Option<&mut (dyn FnMut(&str) + '_)>,
+) -> Result<()> {
     let mut cmd = Command::new(command);
     cmd.current_dir(path.clone());
     cmd.args(args);
+    cmd.envs(envs.iter().map(|(k, v)| (k, v)));
+    if on_stdout.is_some() {
+        cmd.stdout(Stdio::piped());
+    }
 
-    trace!("running `{cmd:?}` at {path:?}");
+    let line = format_command(&cmd);
+    trace!("running `{line}` in {path:?}");
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to run `{line}` in {path:?}: {e}"))?;
+
+    let lines = child.stdout.take().map(|stdout| {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    });
+    let mut forward = |lines: &mpsc::Receiver<String>, wait: bool| {
+        if let Some(on_stdout) = on_stdout.as_deref_mut() {
+            if wait {
+                lines.iter().for_each(|line| on_stdout(&line));
+            } else {
+                lines.try_iter().for_each(|line| on_stdout(&line));
+            }
+        }
+    };
 
-    let status = cmd
-        .status()
-        .map_err(|e| anyhow::anyhow!("failed to run command `{cmd:?}: {e}` at {path:?}"))?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let status = loop {
+        if let Some(lines) = &lines {
+            forward(lines, false);
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            child.kill()?;
+            child.wait()?;
+            return Err(anyhow::anyhow!(
+                "`{line}` in {path:?} timed out after {}s",
+                timeout.unwrap_or_default().as_secs()
+            ));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if let Some(lines) = &lines {
+        forward(lines, true);
+    }
 
     if !status.success() {
         return Err(anyhow::anyhow!(
-            "command exited with code {:?}",
-            status.code()
+            "`{line}` in {path:?} exited with code {}",
+            status
+                .code()
+                .map_or_else(|| "none".to_string(), |c| c.to_string())
         ));
     }
 
     Ok(())
 }
 
-pub(crate) fn get_cargo_metadata(path: &PathBuf) -> Result<Value> {
-    let output = Command::new("cargo")
+pub(crate) fn run_command_output(path: &PathBuf, command: &str, args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new(command);
+    cmd.current_dir(path.clone());
+    cmd.args(args);
+
+    let line = format_command(&cmd);
+    trace!("running `{line}` in {path:?}");
+
+    let output = cmd
+        .output()
+        .map_err(|e| anyhow::anyhow!("failed to run `{line}` in {path:?}: {e}"))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "`{line}` in {path:?} exited with code {}: {}",
+            output
+                .status
+                .code()
+                .map_or_else(|| "none".to_string(), |c| c.to_string()),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Runs `cargo metadata`, only the workspace members are listed unless `include_deps` is
+/// set, which also resolves the dependency graph and is a lot slower.
+pub(crate) fn get_cargo_metadata(path: &PathBuf, include_deps: bool) -> Result<Value> {
+    let mut command = Command::new("cargo");
+    command
         .current_dir(path)
-        .args(["metadata", "--format-version", "1", "--no-deps"])
-        .output()?;
+        .args(["metadata", "--format-version", "1"]);
+    if !include_deps {
+        command.arg("--no-deps");
+    }
+    let output = command.output()?;
     let stdout = String::from_utf8(output.stdout)?;
     if stdout.is_empty() {
         return Err(anyhow!("no cargo metadata found"));
@@ -52,58 +242,237 @@ pub(crate) fn get_target_directory(metadata: &Value) -> PathBuf {
 }
 
 pub(crate) fn get_project_name(path: &Path, metadata: &Value) -> Result<String> {
-    let manifest = path.join("Cargo.toml");
-    let manifest_abs = fs::canonicalize(&manifest)?;
+    Ok(get_package(path, metadata)?["name"]
+        .as_str()
+        .expect("failed to cast project name to a string")
+        .to_string())
+}
+
+pub(crate) fn get_project_version(path: &Path, metadata: &Value) -> Result<String> {
+    Ok(get_package(path, metadata)?["version"]
+        .as_str()
+        .expect("failed to cast project version to a string")
+        .to_string())
+}
 
-    let pkg = metadata["packages"]
+/// Name of the package's lib target, which names the wasm and can differ from the package.
+pub(crate) fn get_lib_name(path: &Path, metadata: &Value) -> Result<Option<String>> {
+    Ok(get_package(path, metadata)?["targets"]
         .as_array()
-        .unwrap()
-        .iter()
-        .find(|p| {
-            p["manifest_path"].as_str().map_or_else(
-                || false,
-                |m| {
-                    let pkg_path = Path::new(m);
-                    fs::canonicalize(pkg_path).map_or_else(
-                        |_| m == manifest.to_str().unwrap_or_default(),
-                        |pkg_abs| pkg_abs == manifest_abs,
-                    )
-                },
-            )
+        .into_iter()
+        .flatten()
+        .find(|target| {
+            target["kind"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().any(|kind| kind == "cdylib" || kind == "lib"))
         })
-        .expect("package not found");
+        .and_then(|target| target["name"].as_str())
+        .map(str::to_string))
+}
 
-    Ok(pkg["name"]
-        .as_str()
-        .expect("failed to cast project name to a string")
-        .to_string())
+pub(crate) fn depends_on(path: &Path, metadata: &Value, dependency: &str) -> Result<bool> {
+    Ok(get_package(path, metadata)?["dependencies"]
+        .as_array()
+        .is_some_and(|deps| deps.iter().any(|d| d["name"].as_str() == Some(dependency))))
+}
+
+fn find_package<'a>(path: &Path, metadata: &'a Value) -> Result<Option<&'a Value>> {
+    let manifest = path.join("Cargo.toml");
+    let manifest_abs = fs::canonicalize(&manifest)?;
+
+    Ok(metadata["packages"].as_array().unwrap().iter().find(|p| {
+        p["manifest_path"].as_str().map_or_else(
+            || false,
+            |m| {
+                let pkg_path = Path::new(m);
+                fs::canonicalize(pkg_path).map_or_else(
+                    |_| m == manifest.to_str().unwrap_or_default(),
+                    |pkg_abs| pkg_abs == manifest_abs,
+                )
+            },
+        )
+    }))
+}
+
+pub(crate) fn get_package<'a>(path: &Path, metadata: &'a Value) -> Result<&'a Value> {
+    Ok(find_package(path, metadata)?.expect("package not found"))
+}
+
+pub(crate) fn validate_crate_name(name: &str, pattern: &Regex) -> Result<String> {
+    let name = name.trim();
+    if pattern.is_match(name) {
+        return Ok(name.to_string());
+    }
+
+    let mut offending = name
+        .chars()
+        .filter(|c| !pattern.is_match(&c.to_string()))
+        .map(|c| format!("{c:?}"))
+        .collect::<Vec<_>>();
+    offending.dedup();
+
+    if offending.is_empty() {
+        return Err(anyhow!(
+            "crate name {name:?} does not match the pattern `{pattern}`"
+        ));
+    }
+
+    Err(anyhow!(
+        "crate name {name:?} does not match the pattern `{pattern}`, offending characters: {}",
+        offending.join(", ")
+    ))
+}
+
+/// Finds the package's manifest, `crate.json` or `crate.json5` when comments are wanted.
+pub(crate) fn crate_json_path(path: &Path) -> Result<Option<PathBuf>> {
+    let json = path.join("crate.json");
+    let json5 = path.join("crate.json5");
+
+    match (json.exists(), json5.exists()) {
+        (true, true) => Err(anyhow!(
+            "both crate.json and crate.json5 exist in {path:?}, remove one of them"
+        )),
+        (true, false) => Ok(Some(json)),
+        (false, true) => Ok(Some(json5)),
+        (false, false) => Ok(None),
+    }
+}
+
+/// Reads the package's manifest, a `crate.json5` is parsed as JSON5 so it may contain
+/// comments, trailing commas and unquoted keys.
+pub(crate) fn read_crate_json(path: &Path) -> Result<Option<Value>> {
+    let Some(manifest) = crate_json_path(path)? else {
+        return Ok(None);
+    };
+    let contents = fs::read_to_string(&manifest)?;
+
+    let crate_json = if manifest.extension().is_some_and(|e| e == "json5") {
+        json5::from_str(&contents).map_err(|e| anyhow!("failed to parse crate.json5: {e}"))?
+    } else {
+        serde_json::from_str(&contents).map_err(|e| anyhow!("failed to parse crate.json: {e}"))?
+    };
+
+    Ok(Some(crate_json))
 }
 
 pub(crate) fn get_wasm_path(
     path: &Path,
+    target: &str,
     release: bool,
+    crate_type: CrateType,
     metadata: &Value,
 ) -> Result<(String, PathBuf)> {
     let profile = if release { "release" } else { "debug" };
 
     let project_name = get_project_name(path, metadata)?;
-    let filename = format!("{project_name}.wasm");
+    let filename = match crate_type {
+        // cargo replaces dashes with underscores in lib artifact filenames
+        CrateType::Cdylib => format!(
+            "{}.wasm",
+            get_lib_name(path, metadata)?
+                .unwrap_or(project_name)
+                .replace('-', "_")
+        ),
+        // bins keep the package name as-is
+        CrateType::Bin => format!("{project_name}.wasm"),
+    };
 
     let target_directory = get_target_directory(metadata);
 
     // target/wasm32-unknown-unknown/release/mycrate.wasm
     Ok((
         filename.clone(),
-        target_directory.join(TARGET).join(profile).join(filename),
+        target_directory.join(target).join(profile).join(filename),
     ))
 }
 
+fn package_dirs(metadata: &Value, ids: &str) -> Vec<PathBuf> {
+    let members = metadata[ids]
+        .as_array()
+        .map(|arr| arr.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    metadata["packages"]
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter(|p| p["id"].as_str().is_some_and(|id| members.contains(&id)))
+                .filter_map(|p| p["manifest_path"].as_str())
+                .filter_map(|m| Path::new(m).parent().map(Path::to_path_buf))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn get_workspace_members(metadata: &Value) -> Vec<PathBuf> {
+    package_dirs(metadata, "workspace_members")
+}
+
+/// The feature resolver of the workspace, set in `[workspace]` or `[package]` of the root
+/// manifest and otherwise implied by the root package's edition, `1` for virtual manifests.
+pub(crate) fn workspace_resolver(metadata: &Value) -> Result<String> {
+    let manifest_path =
+        Path::new(metadata["workspace_root"].as_str().unwrap_or_default()).join("Cargo.toml");
+    let manifest = fs::read_to_string(&manifest_path)?
+        .parse::<DocumentMut>()
+        .map_err(|e| anyhow!("failed to parse {manifest_path:?}: {e}"))?;
+
+    let resolver = manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("resolver"))
+        .or_else(|| manifest.get("package")?.get("resolver"))
+        .and_then(Item::as_str);
+    if let Some(resolver) = resolver {
+        return Ok(resolver.to_string());
+    }
+
+    let edition = manifest.get("package").and_then(|package| {
+        let edition = package.get("edition")?;
+        // `edition.workspace = true` inherits `[workspace.package]`
+        match edition.as_str() {
+            Some(edition) => Some(edition),
+            None => manifest
+                .get("workspace")?
+                .get("package")?
+                .get("edition")?
+                .as_str(),
+        }
+    });
+    Ok(match edition {
+        Some("2024") => "3",
+        Some("2021") => "2",
+        _ => "1",
+    }
+    .to_string())
+}
+
+/// When `path` is a virtual workspace manifest, returns the `default-members` cargo builds
+/// there without `--package` or `--workspace`.
+pub(crate) fn get_default_members(path: &PathBuf) -> Result<Option<Vec<PathBuf>>> {
+    if !path.join("Cargo.toml").exists() {
+        return Ok(None);
+    }
+    let metadata = get_cargo_metadata(path, false)?;
+    if find_package(path, &metadata)?.is_some() {
+        return Ok(None);
+    }
+
+    let members = package_dirs(&metadata, "workspace_default_members");
+    if members.is_empty() {
+        anyhow::bail!(
+            "{path:?} is a virtual workspace manifest without default members, pass a package or --workspace, or set `default-members` in [workspace]"
+        );
+    }
+
+    Ok(Some(members))
+}
+
 pub(crate) fn resolve_project_dir(path: &PathBuf, package_name: Option<&str>) -> Result<PathBuf> {
     if !fs::exists(path.join("Cargo.toml"))? {
         return Ok(path.clone());
     }
 
-    let metadata = get_cargo_metadata(path)?;
+    let metadata = get_cargo_metadata(path, false)?;
 
     let manifest = package_name
         .map_or_else(
@@ -147,9 +516,17 @@ pub(crate) fn resolve_path_and_package(arg: Option<String>) -> Result<(PathBuf,
     Ok((cwd, None))
 }
 
+pub fn get_gooseboy_home() -> Result<PathBuf> {
+    if let Ok(home) = env::var("GOOSEBOY_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+
+    let home = env::var("HOME").or_else(|_| env::var("USERPROFILE"))?;
+    Ok(Path::new(&home).join(".gooseboy"))
+}
+
 pub fn get_gooseboy_crates_folder() -> Result<PathBuf> {
-    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"))?;
-    let folder = Path::new(&home).join(".gooseboy").join("crates");
+    let folder = get_gooseboy_home()?.join("crates");
 
     if !folder.exists() {
         fs::create_dir_all(&folder)?;
@@ -158,7 +535,79 @@ pub fn get_gooseboy_crates_folder() -> Result<PathBuf> {
     Ok(folder)
 }
 
-pub fn copy_crate(crate_path: &PathBuf, destination_path: &Path) -> Result<()> {
+/// Crates built for a target other than the default go into a `<target>/` subfolder so
+/// builds of the same crate for different targets don't overwrite each other.
+pub fn get_target_crates_folder(target: &str) -> Result<PathBuf> {
+    get_gooseboy_crates_folder()?;
+    target_crates_path(target)
+}
+
+/// Like [`get_target_crates_folder`] but without creating the crates folder.
+pub(crate) fn target_crates_path(target: &str) -> Result<PathBuf> {
+    let folder = get_gooseboy_home()?.join("crates");
+    if target == TARGET {
+        return Ok(folder);
+    }
+
+    Ok(folder.join(target))
+}
+
+/// Whether a subfolder of the crates folder is the `<target>/` folder of another target.
+pub(crate) fn is_target_folder(name: &str) -> bool {
+    name.starts_with("wasm32-") || name.starts_with("wasm64-")
+}
+
+/// The crates installed in `folder` and its `<target>/` subfolders along with the target
+/// each was installed for, sorted by path. `-latest` links are skipped since they point at
+/// a crate that is already listed.
+pub(crate) fn installed_crates(folder: &Path) -> Result<Vec<(PathBuf, String)>> {
+    fn scan(folder: &Path, target: &str, crates: &mut Vec<(PathBuf, String)>) -> Result<()> {
+        for entry in fs::read_dir(folder).map_err(|e| anyhow!("failed to read {folder:?}: {e}"))? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() && target == TARGET {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if is_target_folder(&name) {
+                    scan(&path, &name, crates)?;
+                }
+            } else if file_type.is_file() && path.extension().is_some_and(|e| e == "gbcrate") {
+                crates.push((path, target.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut crates = Vec::new();
+    scan(folder, TARGET, &mut crates)?;
+    crates.sort();
+
+    Ok(crates)
+}
+
+/// Fails with a clear error when files can't be created in `folder`, e.g. on a read-only mount.
+pub(crate) fn ensure_writable(folder: &Path) -> Result<()> {
+    let not_writable = |e: std::io::Error| anyhow!("destination is not writable: {folder:?} ({e})");
+
+    fs::create_dir_all(folder).map_err(not_writable)?;
+    if fs::metadata(folder)
+        .map_err(not_writable)?
+        .permissions()
+        .readonly()
+    {
+        return Err(anyhow!(
+            "destination is not writable: {folder:?} (read-only)"
+        ));
+    }
+    tempfile::tempfile_in(folder).map_err(not_writable)?;
+
+    Ok(())
+}
+
+/// Copies the crate into `destination_path` and returns the path of the copy.
+pub fn copy_crate(crate_path: &PathBuf, destination_path: &Path) -> Result<PathBuf> {
     let dst = destination_path.join(
         crate_path
             .file_name()
@@ -171,11 +620,139 @@ pub fn copy_crate(crate_path: &PathBuf, destination_path: &Path) -> Result<()> {
 
     trace!("copying {crate_path:?} to {dst:?}");
 
-    fs::create_dir_all(
-        dst.parent()
-            .ok_or_else(|| anyhow::anyhow!("failed to get copy destination parent"))?,
+    let folder = dst
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("failed to get copy destination parent"))?;
+    ensure_writable(folder)?;
+    let copy = || -> Result<()> {
+        let mut temp = crate_temp_file(folder, false)?;
+        io::copy(&mut File::open(crate_path)?, temp.as_file_mut())?;
+        temp.persist(&dst)?;
+        Ok(())
+    };
+    copy().map_err(|e| anyhow!("failed to copy the crate to {dst:?}: {e}"))?;
+
+    Ok(dst)
+}
+
+/// Creates a `.gbcrate-*` temporary file in `folder` with the permissions of a regular file,
+/// crates are written to one and only moved into place once complete.
+pub(crate) fn crate_temp_file(folder: &Path, keep: bool) -> Result<NamedTempFile> {
+    let mut temp = tempfile::Builder::new();
+    temp.prefix(".gbcrate-").disable_cleanup(keep);
+    // temp files are private by default, the crate should get the usual permissions
+    #[cfg(unix)]
+    temp.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o644));
+
+    temp.tempfile_in(folder)
+        .map_err(|e| anyhow!("failed to create a temporary file in {folder:?}: {e}"))
+}
+
+/// Moves a finished temporary crate to `dst`, going through a temporary copy next to it when
+/// it can't be renamed, e.g. from a staging directory on another file system.
+pub(crate) fn persist_crate(temp: NamedTempFile, dst: &Path) -> Result<()> {
+    let Err(e) = temp.persist(dst) else {
+        return Ok(());
+    };
+    trace!(
+        "failed to move {:?} to {dst:?}, copying it instead: {}",
+        e.file.path(),
+        e.error
+    );
+
+    let folder = dst
+        .parent()
+        .ok_or_else(|| anyhow!("failed to get the parent of {dst:?}"))?;
+    let mut local = crate_temp_file(folder, false)?;
+    io::copy(&mut e.file.reopen()?, local.as_file_mut())?;
+    local.persist(dst)?;
+
+    Ok(())
+}
+
+/// Points `<name>-latest.gbcrate` next to `installed` at it, a symlink where supported and
+/// a copy elsewhere.
+pub(crate) fn link_latest(installed: &Path, name: &str) -> Result<()> {
+    let link = installed.with_file_name(format!("{name}-latest.gbcrate"));
+    if link == installed {
+        return Ok(());
+    }
+
+    if fs::symlink_metadata(&link).is_ok() {
+        fs::remove_file(&link)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(
+        installed
+            .file_name()
+            .ok_or_else(|| anyhow!("failed to get crate filename"))?,
+        &link,
     )?;
-    fs::copy(crate_path, dst)?;
+    #[cfg(not(unix))]
+    fs::copy(installed, &link)?;
 
+    trace!("linked {link:?} to {installed:?}");
     Ok(())
 }
+
+/// Splits `ssh://user@host:/path` (or `ssh://user@host/path`) into the host and the path.
+pub(crate) fn parse_ssh_destination(destination: &str) -> Option<(&str, &str)> {
+    let rest = destination.strip_prefix("ssh://")?;
+    let split = rest.find([':', '/'])?;
+    let (host, path) = rest.split_at(split);
+    let path = path.strip_prefix(':').unwrap_or(path);
+
+    (!host.is_empty() && !path.is_empty()).then_some((host, path))
+}
+
+/// Uploads the crate to `host:path` by piping it through `ssh`, creating the folder first.
+/// The remote shell sees both commands, so the path is quoted for it.
+pub fn upload_crate(crate_path: &Path, host: &str, path: &str) -> Result<()> {
+    let filename = crate_path
+        .file_name()
+        .ok_or_else(|| anyhow!("failed to get crate filename"))?
+        .to_string_lossy();
+    let cwd = env::current_dir()?;
+    let hint = |e: anyhow::Error| {
+        anyhow!(
+            "{e}\nmake sure `ssh {host}` works without prompting, e.g. by adding your key with `ssh-copy-id {host}`"
+        )
+    };
+
+    let folder = match path.trim_end_matches('/') {
+        "" => "/",
+        folder => folder,
+    };
+    let folder = remote_path_quote(folder);
+    run_command(
+        &cwd,
+        "ssh",
+        &["-o", "BatchMode=yes", host, "mkdir", "-p", "--", &folder],
+    )
+    .map_err(hint)?;
+
+    let mut cmd = Command::new("ssh");
+    cmd.args([
+        "-o",
+        "BatchMode=yes",
+        host,
+        &format!("cat > {folder}/{}", shell_quote(&filename)),
+    ]);
+    cmd.stdin(File::open(crate_path)?);
+    let line = format_command(&cmd);
+    trace!("running `{line}`");
+    let status = cmd
+        .status()
+        .map_err(|e| hint(anyhow!("failed to run `{line}`: {e}")))?;
+    if !status.success() {
+        return Err(hint(anyhow!("`{line}` exited with {status}")));
+    }
+
+    info!("uploaded {filename} to {host}:{path}");
+    Ok(())
+}
+
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
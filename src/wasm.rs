@@ -0,0 +1,395 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{Result, anyhow, bail};
+use wasmparser::{
+    ExternalKind, KnownCustom, Name, Parser, Payload, TypeRef, Validator, WasmFeatures,
+};
+
+const UNWIND_MARKERS: [&str; 3] = ["_Unwind_", "__cxa_", "panic_unwind"];
+
+/// Post-MVP proposals worth reporting, named like the matching rustc target features.
+const PROPOSALS: [(&str, WasmFeatures); 15] = [
+    ("mutable-globals", WasmFeatures::MUTABLE_GLOBAL),
+    ("sign-ext", WasmFeatures::SIGN_EXTENSION),
+    ("nontrapping-fptoint", WasmFeatures::SATURATING_FLOAT_TO_INT),
+    ("bulk-memory", WasmFeatures::BULK_MEMORY),
+    ("reference-types", WasmFeatures::REFERENCE_TYPES),
+    ("multivalue", WasmFeatures::MULTI_VALUE),
+    ("simd128", WasmFeatures::SIMD),
+    ("relaxed-simd", WasmFeatures::RELAXED_SIMD),
+    ("atomics", WasmFeatures::THREADS),
+    ("tail-call", WasmFeatures::TAIL_CALL),
+    ("exception-handling", WasmFeatures::EXCEPTIONS),
+    ("multimemory", WasmFeatures::MULTI_MEMORY),
+    ("memory64", WasmFeatures::MEMORY64),
+    ("extended-const", WasmFeatures::EXTENDED_CONST),
+    ("gc", WasmFeatures::GC),
+];
+
+/// Validates the module and returns its number of functions, imported ones included.
+pub(crate) fn validate_wasm(wasm: &[u8]) -> Result<u32> {
+    let types = wasmparser::validate(wasm)
+        .map_err(|e| anyhow!("invalid wasm at offset {:#x}: {}", e.offset(), e.message()))?;
+
+    Ok(types.as_ref().function_count())
+}
+
+/// Removes every custom section called `name`, copying the rest of the module byte for byte.
+pub(crate) fn strip_custom_section(wasm: &[u8], name: &str) -> Result<Vec<u8>> {
+    let mut stripped = Vec::with_capacity(wasm.len());
+    // sections are contiguous, each one's id and size come right after the previous one
+    let mut section_start = 0;
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))?;
+        let end = match &payload {
+            Payload::Version { range, .. } => range.end,
+            payload => match payload.as_section() {
+                Some((_, range)) => range.end,
+                None => continue,
+            },
+        } as usize;
+
+        if !matches!(&payload, Payload::CustomSection(reader) if reader.name() == name) {
+            stripped.extend_from_slice(&wasm[section_start..end]);
+        }
+        section_start = end;
+    }
+
+    Ok(stripped)
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Appends a custom section, custom sections can go anywhere so the module stays valid.
+pub(crate) fn append_custom_section(wasm: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let mut contents = Vec::with_capacity(name.len() + data.len() + 5);
+    write_leb128(&mut contents, name.len());
+    contents.extend_from_slice(name.as_bytes());
+    contents.extend_from_slice(data);
+
+    wasm.push(0);
+    write_leb128(wasm, contents.len());
+    wasm.extend_from_slice(&contents);
+}
+
+/// Lists the post-MVP proposals the module relies on, found by checking which ones it
+/// stops validating without.
+pub(crate) fn required_features(wasm: &[u8]) -> Vec<&'static str> {
+    PROPOSALS
+        .iter()
+        .filter(|(_, feature)| {
+            Validator::new_with_features(WasmFeatures::all().difference(*feature))
+                .validate_all(wasm)
+                .is_err()
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Returns every import of the module formatted as `module::name`.
+pub(crate) fn read_imports(wasm: &[u8]) -> Result<Vec<String>> {
+    let mut imports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))?;
+        if let Payload::ImportSection(reader) = payload {
+            for import in reader.into_imports() {
+                let import = import.map_err(|e| anyhow!("failed to parse wasm import: {e}"))?;
+                imports.push(format!("{}::{}", import.module, import.name));
+            }
+        }
+    }
+
+    Ok(imports)
+}
+
+const VALUE_TYPES: [&str; 7] = ["i32", "i64", "f32", "f64", "v128", "funcref", "externref"];
+
+/// Params and results of a function, written like `(i32, i32) -> i32`, the arrow is left
+/// out when there are no results.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub params: Vec<String>,
+    pub results: Vec<String>,
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        fn types(list: &str) -> Result<Vec<String>> {
+            let list = list.trim();
+            let list = list
+                .strip_prefix('(')
+                .and_then(|l| l.strip_suffix(')'))
+                .unwrap_or(list);
+
+            list.split(',')
+                .map(str::trim)
+                .filter(|ty| !ty.is_empty())
+                .map(|ty| {
+                    if !VALUE_TYPES.contains(&ty) {
+                        bail!(
+                            "unknown value type `{ty}`, expected one of {}",
+                            VALUE_TYPES.join(", ")
+                        );
+                    }
+                    Ok(ty.to_string())
+                })
+                .collect()
+        }
+
+        let (params, results) = s.split_once("->").unwrap_or((s, ""));
+        if !params.trim().starts_with('(') || !params.trim().ends_with(')') {
+            bail!("expected the params in parentheses, e.g. `(i32, i32) -> i32`");
+        }
+
+        Ok(Self {
+            params: types(params)?,
+            results: types(results)?,
+        })
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.params.join(", "))?;
+        match self.results.as_slice() {
+            [] => Ok(()),
+            [result] => write!(f, " -> {result}"),
+            results => write!(f, " -> ({})", results.join(", ")),
+        }
+    }
+}
+
+/// Maps the name of every exported function to its signature.
+pub(crate) fn export_signatures(wasm: &[u8]) -> Result<BTreeMap<String, Signature>> {
+    let mut types = Vec::new();
+    let mut functions = Vec::new();
+    let mut exports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))? {
+            Payload::TypeSection(reader) => {
+                for ty in reader.into_iter_err_on_gc_types() {
+                    types.push(ty.map_err(|e| anyhow!("failed to parse wasm type: {e}"))?);
+                }
+            }
+            // imported functions come first in the function index space
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    let import = import.map_err(|e| anyhow!("failed to parse wasm import: {e}"))?;
+                    if let TypeRef::Func(ty) | TypeRef::FuncExact(ty) = import.ty {
+                        functions.push(ty);
+                    }
+                }
+            }
+            Payload::FunctionSection(reader) => {
+                for ty in reader {
+                    functions.push(ty.map_err(|e| anyhow!("failed to parse wasm function: {e}"))?);
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for e in reader {
+                    let e = e.map_err(|e| anyhow!("failed to parse wasm export: {e}"))?;
+                    if matches!(e.kind, ExternalKind::Func | ExternalKind::FuncExact) {
+                        exports.push((e.name.to_string(), e.index));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    exports
+        .into_iter()
+        .map(|(name, index)| {
+            let ty = functions
+                .get(index as usize)
+                .and_then(|ty| types.get(*ty as usize))
+                .ok_or_else(|| anyhow!("export `{name}` refers to a missing function"))?;
+            let signature = Signature {
+                params: ty.params().iter().map(ToString::to_string).collect(),
+                results: ty.results().iter().map(ToString::to_string).collect(),
+            };
+            Ok((name, signature))
+        })
+        .collect()
+}
+
+/// Looks up the signature of the exported function `name`.
+pub(crate) fn export_signature(wasm: &[u8], name: &str) -> Result<Option<Signature>> {
+    Ok(export_signatures(wasm)?.remove(name))
+}
+
+/// Returns the names of the exported functions, leaving out memories, tables and globals.
+pub(crate) fn read_function_exports(wasm: &[u8]) -> Result<Vec<String>> {
+    let mut exports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))?;
+        if let Payload::ExportSection(reader) = payload {
+            for export in reader {
+                let export = export.map_err(|e| anyhow!("failed to parse wasm export: {e}"))?;
+                if matches!(export.kind, ExternalKind::Func | ExternalKind::FuncExact) {
+                    exports.push(export.name.to_string());
+                }
+            }
+        }
+    }
+
+    exports.sort();
+    Ok(exports)
+}
+
+/// An export of the module, functions come with their signature.
+pub(crate) struct Export {
+    pub name: String,
+    pub kind: &'static str,
+    pub signature: Option<Signature>,
+}
+
+/// Returns every export with its kind, sorted by name.
+pub(crate) fn read_export_details(wasm: &[u8]) -> Result<Vec<Export>> {
+    let mut signatures = export_signatures(wasm)?;
+    let mut exports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))?;
+        if let Payload::ExportSection(reader) = payload {
+            for export in reader {
+                let export = export.map_err(|e| anyhow!("failed to parse wasm export: {e}"))?;
+                let kind = match export.kind {
+                    ExternalKind::Func | ExternalKind::FuncExact => "function",
+                    ExternalKind::Table => "table",
+                    ExternalKind::Memory => "memory",
+                    ExternalKind::Global => "global",
+                    ExternalKind::Tag => "tag",
+                };
+                exports.push(Export {
+                    name: export.name.to_string(),
+                    kind,
+                    signature: signatures.remove(export.name),
+                });
+            }
+        }
+    }
+
+    exports.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(exports)
+}
+
+/// Returns the names of every export, sorted so listings are stable across builds.
+pub(crate) fn read_exports(wasm: &[u8]) -> Result<Vec<String>> {
+    let mut exports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))?;
+        if let Payload::ExportSection(reader) = payload {
+            for export in reader {
+                let export = export.map_err(|e| anyhow!("failed to parse wasm export: {e}"))?;
+                exports.push(export.name.to_string());
+            }
+        }
+    }
+
+    exports.sort();
+    Ok(exports)
+}
+
+/// The size in bytes of every function body, locals included, named from the `name` section
+/// or `func[<index>]` when the module has none. Sorted from largest to smallest.
+pub(crate) fn function_sizes(wasm: &[u8]) -> Result<Vec<(String, u64)>> {
+    let mut imported = 0;
+    let mut bodies = Vec::new();
+    let mut names = BTreeMap::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))? {
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    let import = import.map_err(|e| anyhow!("failed to parse wasm import: {e}"))?;
+                    if matches!(import.ty, TypeRef::Func(_) | TypeRef::FuncExact(_)) {
+                        imported += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let range = body.range();
+                bodies.push(range.end - range.start);
+            }
+            Payload::CustomSection(reader) => {
+                if let KnownCustom::Name(reader) = reader.as_known() {
+                    for name in reader {
+                        if let Ok(Name::Function(map)) = name {
+                            for naming in map.into_iter().flatten() {
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut sizes = bodies
+        .into_iter()
+        .enumerate()
+        .map(|(i, size)| {
+            let index = imported + i as u32;
+            let name = names
+                .remove(&index)
+                .unwrap_or_else(|| format!("func[{index}]"));
+            (name, size)
+        })
+        .collect::<Vec<_>>();
+    sizes.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+
+    Ok(sizes)
+}
+
+/// Looks for a tag section or unwinding symbols that are only present without `panic = "abort"`.
+pub(crate) fn has_unwinding(wasm: &[u8]) -> Result<bool> {
+    let is_unwind = |name: &str| UNWIND_MARKERS.iter().any(|marker| name.contains(marker));
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.map_err(|e| anyhow!("failed to parse wasm: {e}"))? {
+            Payload::TagSection(_) => return Ok(true),
+            Payload::ImportSection(reader) => {
+                for import in reader.into_imports() {
+                    if is_unwind(import?.name) {
+                        return Ok(true);
+                    }
+                }
+            }
+            Payload::CustomSection(reader) => {
+                if let KnownCustom::Name(names) = reader.as_known() {
+                    for name in names {
+                        if let Name::Function(map) = name? {
+                            for naming in map {
+                                if is_unwind(naming?.name) {
+                                    return Ok(true);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(false)
+}